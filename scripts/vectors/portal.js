@@ -0,0 +1,65 @@
+// The info encoding of the Srun portal pages: xEncode and the s/l packing
+// helpers of srun.portal.js, and the encoder of jquery.base64.js with the
+// alphabet passed in as setAlpha does. Kept close to the page scripts so the
+// Rust port in src/xencode.rs can be checked against them.
+function s(a, b) {
+  var c = a.length, v = [];
+  for (var i = 0; i < c; i += 4)
+    v[i >> 2] = a.charCodeAt(i) | a.charCodeAt(i + 1) << 8 | a.charCodeAt(i + 2) << 16 | a.charCodeAt(i + 3) << 24;
+  if (b) v[v.length] = c;
+  return v;
+}
+function l(a, b) {
+  var d = a.length, c = (d - 1) << 2;
+  if (b) { var m = a[d - 1]; if ((m < c - 3) || (m > c)) return null; c = m; }
+  for (var i = 0; i < d; i++)
+    a[i] = String.fromCharCode(a[i] & 0xff, a[i] >>> 8 & 0xff, a[i] >>> 16 & 0xff, a[i] >>> 24 & 0xff);
+  return b ? a.join('').substring(0, c) : a.join('');
+}
+function xEncode(str, key) {
+  if (str == "") return "";
+  var v = s(str, true), k = s(key, false);
+  if (k.length < 4) k.length = 4;
+  var n = v.length - 1, z = v[n], y = v[0], c = 0x86014019 | 0x183639A0, m, e, p,
+      q = Math.floor(6 + 52 / (n + 1)), d = 0;
+  while (0 < q--) {
+    d = d + c & (0x8CE0D9BF | 0x731F2640);
+    e = d >>> 2 & 3;
+    for (p = 0; p < n; p++) {
+      y = v[p + 1];
+      m = z >>> 5 ^ y << 2;
+      m += (y >>> 3 ^ z << 4) ^ (d ^ y);
+      m += k[(p & 3) ^ e] ^ z;
+      z = v[p] = v[p] + m & (0xEFB8D130 | 0x10472ECF);
+    }
+    y = v[0];
+    m = z >>> 5 ^ y << 2;
+    m += (y >>> 3 ^ z << 4) ^ (d ^ y);
+    m += k[(p & 3) ^ e] ^ z;
+    z = v[n] = v[n] + m & (0xBB390742 | 0x44C6F8BD);
+  }
+  return l(v, false);
+}
+function b64(alpha, s) {
+  var PADCHAR = "=", i, b10, x = [], imax = s.length - s.length % 3;
+  function g(s, i) { var x = s.charCodeAt(i); if (x > 255) throw "INVALID_CHARACTER_ERR"; return x; }
+  if (s.length === 0) return s;
+  for (i = 0; i < imax; i += 3) {
+    b10 = (g(s, i) << 16) | (g(s, i + 1) << 8) | g(s, i + 2);
+    x.push(alpha.charAt(b10 >> 18)); x.push(alpha.charAt((b10 >> 12) & 63));
+    x.push(alpha.charAt((b10 >> 6) & 63)); x.push(alpha.charAt(b10 & 63));
+  }
+  switch (s.length - imax) {
+    case 1: b10 = g(s, i) << 16;
+      x.push(alpha.charAt(b10 >> 18) + alpha.charAt((b10 >> 12) & 63) + PADCHAR + PADCHAR); break;
+    case 2: b10 = (g(s, i) << 16) | (g(s, i + 1) << 8);
+      x.push(alpha.charAt(b10 >> 18) + alpha.charAt((b10 >> 12) & 63) + alpha.charAt((b10 >> 6) & 63) + PADCHAR); break;
+  }
+  return x.join("");
+}
+const SRUN = "LVoJPiCN2R8G90yg+hmFHuacZ1OWMnrsSTXkYpUq/3dlbfKwv6xztjI7DeBE45QA";
+const STD = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+function info(d, token, enc, prefix, alpha) {
+  return prefix + b64(alpha, xEncode(JSON.stringify({username: d.username, password: d.password, ip: d.ip, acid: d.acid, enc_ver: enc}), token));
+}
+module.exports = { xEncode, b64, info, SRUN, STD, s, l };
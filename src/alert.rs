@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::hooks;
+use crate::srun::InfoResponse;
+use crate::warn;
+
+/// Exit code of `status` when a threshold is crossed.
+pub const EXIT_THRESHOLD: i32 = 3;
+
+/// Limits on the account state reported by `rad_user_info`. Unset limits and
+/// values the portal does not report are not checked.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Thresholds {
+    /// Alert when `user_balance` drops below this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_balance: Option<f64>,
+    /// Alert when `sum_bytes` (usage in the accounting period) exceeds this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sum_bytes: Option<u64>,
+    /// Alert when `remain_seconds` drops below this, 0 means unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_remain_seconds: Option<u64>,
+    /// Shell command run when a threshold is crossed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook: Option<String>,
+}
+
+/// A crossed threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl Thresholds {
+    pub fn is_empty(&self) -> bool {
+        self.min_balance.is_none()
+            && self.max_sum_bytes.is_none()
+            && self.min_remain_seconds.is_none()
+            && self.hook.is_none()
+    }
+
+    /// These thresholds, with the limits left unset taken from `fallback`.
+    pub fn or(&self, fallback: &Thresholds) -> Thresholds {
+        Thresholds {
+            min_balance: self.min_balance.or(fallback.min_balance),
+            max_sum_bytes: self.max_sum_bytes.or(fallback.max_sum_bytes),
+            min_remain_seconds: self.min_remain_seconds.or(fallback.min_remain_seconds),
+            hook: self.hook.clone().or_else(|| fallback.hook.clone()),
+        }
+    }
+
+    pub fn check(&self, info: &InfoResponse) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        if let (Some(min), Some(balance)) = (self.min_balance, info.user_balance)
+            && balance < min
+        {
+            alerts.push(Alert {
+                kind: "balance",
+                message: format!("balance {} is below {}", balance, min),
+            });
+        }
+        if let (Some(max), Some(sum_bytes)) = (self.max_sum_bytes, info.sum_bytes)
+            && sum_bytes > max
+        {
+            alerts.push(Alert {
+                kind: "sum_bytes",
+                message: format!("used {} bytes, above {}", sum_bytes, max),
+            });
+        }
+        if let (Some(min), Some(remain)) = (self.min_remain_seconds, info.remain_seconds)
+            && remain > 0
+            && remain < min
+        {
+            alerts.push(Alert {
+                kind: "remain_seconds",
+                message: format!("{} seconds remaining, below {}", remain, min),
+            });
+        }
+        alerts
+    }
+
+    /// Logs the alerts and starts the hook without waiting for it.
+    pub fn report(
+        &self,
+        username: &str,
+        alerts: &[Alert],
+        timeout: Duration,
+    ) -> Option<JoinHandle<()>> {
+        for alert in alerts {
+            warn!("Threshold crossed for {}: {}", username, alert.message);
+        }
+        let hook = self.hook.as_ref().filter(|_| !alerts.is_empty())?;
+        let kinds: Vec<&str> = alerts.iter().map(|alert| alert.kind).collect();
+        let messages: Vec<&str> = alerts.iter().map(|alert| alert.message.as_str()).collect();
+        let env = [
+            ("SRUN_USER", username.to_string()),
+            ("SRUN_ALERTS", kinds.join(",")),
+            ("SRUN_ALERT_MESSAGE", messages.join("; ")),
+        ];
+        hooks::spawn(hook, env, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let thresholds = Thresholds {
+            min_balance: Some(5.0),
+            max_sum_bytes: Some(10 << 30),
+            min_remain_seconds: Some(3600),
+            hook: None,
+        };
+        let info = InfoResponse {
+            user_balance: Some(4.5),
+            sum_bytes: Some(12 << 30),
+            remain_seconds: Some(1800),
+            ..Default::default()
+        };
+        let kinds: Vec<_> = thresholds.check(&info).iter().map(|a| a.kind).collect();
+        assert_eq!(kinds, ["balance", "sum_bytes", "remain_seconds"]);
+
+        let info = InfoResponse {
+            user_balance: Some(5.0),
+            sum_bytes: Some(10 << 30),
+            remain_seconds: Some(0),
+            ..Default::default()
+        };
+        assert!(thresholds.check(&info).is_empty());
+        assert!(thresholds.check(&InfoResponse::default()).is_empty());
+        assert!(Thresholds::default().check(&info).is_empty());
+    }
+
+    #[test]
+    fn test_or() {
+        let global = Thresholds {
+            min_balance: Some(5.0),
+            max_sum_bytes: Some(10 << 30),
+            hook: Some(String::from("notify")),
+            ..Default::default()
+        };
+        let own = Thresholds {
+            max_sum_bytes: Some(20 << 30),
+            min_remain_seconds: Some(u64::from(u32::MAX) + 1),
+            ..Default::default()
+        };
+        assert_eq!(
+            own.or(&global),
+            Thresholds {
+                min_balance: Some(5.0),
+                max_sum_bytes: Some(20 << 30),
+                min_remain_seconds: Some(u64::from(u32::MAX) + 1),
+                hook: Some(String::from("notify")),
+            }
+        );
+        assert_eq!(Thresholds::default().or(&global), global);
+    }
+}
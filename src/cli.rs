@@ -2,6 +2,10 @@ use clap::{Parser, Subcommand};
 use serde::Deserialize;
 use std::path::PathBuf;
 
+use crate::control::Action;
+use crate::srun::PasswordHash;
+use crate::usage::{Period, ReportFormat};
+
 #[derive(Parser, Deserialize, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
@@ -29,6 +33,14 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub verify_cert: Option<String>,
 
+    /// Client certificate (PEM) for mutual TLS, requires --client-key
+    #[arg(long, global = true)]
+    pub client_cert: Option<String>,
+
+    /// Client private key (PEM) for mutual TLS, requires --client-cert
+    #[arg(long, global = true)]
+    pub client_key: Option<String>,
+
     /// Username
     #[arg(short, long, global = true)]
     pub username: Option<String>,
@@ -37,7 +49,7 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub password: Option<String>,
 
-    /// IP address
+    /// IP address, or the remote session to close for kick and logout
     #[arg(long, global = true)]
     pub ip: Option<String>,
 
@@ -53,6 +65,18 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub enc: Option<String>,
 
+    /// Srun Param - Prefix of the info parameter, default is derived from enc ("srun_bx1" -> "{SRBX1}")
+    #[arg(long, global = true)]
+    pub enc_prefix: Option<String>,
+
+    /// Srun Param - Base64 alphabet of the info parameter: srun (default), standard, or 64 custom characters
+    #[arg(long, global = true)]
+    pub enc_alphabet: Option<String>,
+
+    /// Srun Param - Password hashing mode, default is hmac_md5
+    #[arg(long, global = true, value_enum)]
+    pub password_hash: Option<PasswordHash>,
+
     /// Srun Param - Srun n parameter, default is 200
     #[arg(long, global = true)]
     pub n: Option<u32>,
@@ -84,21 +108,141 @@ pub struct Cli {
     /// Retry interval in milliseconds, default is 500
     #[arg(long, global = true)]
     pub retry_delay: Option<u64>,
+
+    /// Drop another session and retry when login hits the online device limit, default is false
+    #[arg(long, global = true)]
+    pub auto_kick: Option<bool>,
+
+    /// Sessions auto-kick may drop, comma separated, the first one online is dropped
+    #[arg(long, global = true, value_name = "IPS", value_delimiter = ',')]
+    pub auto_kick_ips: Option<Vec<String>>,
+
+    /// Unix socket of the daemon control API, default is None (disabled)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub control_socket: Option<String>,
+
+    /// File keeping the login history and traffic of the users, default is None (disabled)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub state_file: Option<String>,
+
+    /// Print exact numbers instead of sizes, local times and durations
+    #[arg(long, global = true, default_value_t = false)]
+    pub raw: bool,
+
+    /// Connectivity probe URL expected to return 204, default is None (trust the portal)
+    #[arg(long, global = true)]
+    pub probe_url: Option<String>,
 }
 
-#[derive(Subcommand, Debug, Clone)]
+#[derive(Subcommand, Debug, Clone, Default)]
 pub enum Commands {
-    Login,
-    Logout,
+    Login {
+        /// Print the login request instead of sending it
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+        /// Challenge to use for --dry-run instead of fetching one from the server
+        #[arg(long, requires = "dry_run")]
+        challenge: Option<String>,
+        /// Do not redact password and info in --dry-run output
+        #[arg(long, default_value_t = false, requires = "dry_run")]
+        show_secrets: bool,
+    },
+    Logout {
+        /// Terminate every session of the user, not only the one at this address
+        #[arg(long, default_value_t = false)]
+        all: bool,
+    },
+    /// Drop the session of the configured user at the address given by --ip
+    Kick,
+    /// Show the online status of the configured users
+    Status,
     GenConfig {
         #[arg(long, default_value = "./config.json")]
         file: PathBuf,
     },
+    #[default]
     Interfaces,
-}
-
-impl Default for Commands {
-    fn default() -> Self {
-        Commands::Interfaces
-    }
+    /// Decode the info parameter of a captured login request
+    Decode {
+        /// Challenge token returned by get_challenge for that login
+        #[arg(long)]
+        challenge: String,
+        /// Value of the info parameter, including the {SRBX1} prefix
+        info: String,
+    },
+    /// Keep the configured users online, checking their status periodically
+    Daemon {
+        /// Check interval in seconds
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+        /// Also serve Prometheus metrics on this address
+        #[arg(long)]
+        metrics: Option<String>,
+        /// Refresh users as soon as the address of their interface changes (Linux)
+        #[arg(long)]
+        netlink: bool,
+    },
+    /// Query or steer a running daemon through its control socket
+    Ctl {
+        #[arg(value_enum)]
+        action: Action,
+        /// Username to act on, default is all users
+        user: Option<String>,
+    },
+    /// Show recent events and login statistics from the state file
+    History {
+        /// Username to show, default is all users
+        user: Option<String>,
+        /// Number of recent events to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Count events over this many days
+        #[arg(long, default_value_t = 7)]
+        days: u64,
+    },
+    /// Report the traffic of the users per day or month from the state file
+    Usage {
+        /// Username to report, default is all users
+        user: Option<String>,
+        /// First day to report, e.g. 2026-10-01
+        #[arg(long)]
+        since: Option<String>,
+        #[arg(long, value_enum, default_value_t = Period::Day)]
+        period: Period,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// Serve Prometheus metrics of the configured users, refreshed periodically
+    Exporter {
+        /// Address to serve /metrics on
+        #[arg(long, default_value = "127.0.0.1:9750")]
+        listen: String,
+        /// Refresh interval in seconds
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+        /// Log in users found offline
+        #[arg(long, default_value_t = false)]
+        keep_online: bool,
+        /// Refresh users as soon as the address of their interface changes (Linux)
+        #[arg(long)]
+        netlink: bool,
+    },
+    /// Run a mock Srun portal accepting the configured users, for testing
+    MockServer {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Reject these usernames with an arrearage error
+        #[arg(long)]
+        arrears: Vec<String>,
+        /// Send chunked responses
+        #[arg(long, default_value_t = false)]
+        chunked: bool,
+        /// Delay every response by this many milliseconds
+        #[arg(long, default_value_t = 0)]
+        delay: u64,
+        /// Reject logins beyond this many sessions per user
+        #[arg(long)]
+        max_online: Option<usize>,
+    },
 }
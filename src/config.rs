@@ -3,15 +3,25 @@ use std::fmt;
 use std::fs;
 use std::path::PathBuf;
 
+use crate::alert::Thresholds;
 use crate::cli::Cli;
+use crate::daemon::Reload;
 use crate::error::ConfigError;
-use crate::http::CertVerification;
+use crate::hooks::Hooks;
+use crate::http::{CertVerification, ClientAuth};
+use crate::mac;
+use crate::multidial::Multidial;
+use crate::notify::Webhooks;
+use crate::probe::Probe;
+use crate::srun::PasswordHash;
+use crate::state::StateFile;
 use crate::warn;
+use crate::xencode::Encoding;
 use std::error::Error;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct User {
     pub username: String,
     pub password: String,
@@ -19,10 +29,31 @@ pub struct User {
     pub ip: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub iface: Option<String>,
+    /// Hardware address to set on `iface` before it gets an address and logs
+    /// in, e.g. to take over the session slot of a replaced router (Linux)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    /// Obtain the address of `iface` with the built-in DHCP client (Linux)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dhcp: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<Thresholds>,
     #[serde(skip)]
     pub bind_addr: Option<IpAddr>,
 }
 
+impl User {
+    /// Whether both entries log in the same session, so that they only differ
+    /// in settings applied without logging in again. The address of a user
+    /// with an interface follows the interface and is not compared.
+    pub fn same_login(&self, other: &User) -> bool {
+        self.username == other.username
+            && self.password == other.password
+            && self.iface == other.iface
+            && (self.iface.is_some() || self.ip == other.ip)
+    }
+}
+
 impl fmt::Debug for User {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("User")
@@ -30,6 +61,9 @@ impl fmt::Debug for User {
             .field("password", &"******")
             .field("ip", &self.ip)
             .field("iface", &self.iface)
+            .field("mac", &self.mac)
+            .field("dhcp", &self.dhcp)
+            .field("thresholds", &self.thresholds)
             .finish()
     }
 }
@@ -41,9 +75,18 @@ pub struct Config {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub server_ip: Option<String>,
     pub verify_cert: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
     pub users: Vec<User>,
     pub strict_bind: bool,
     pub enc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enc_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enc_alphabet: Option<String>,
+    pub password_hash: PasswordHash,
     pub n: u32,
     pub r#type: u32,
     pub acid: u32,
@@ -52,8 +95,29 @@ pub struct Config {
     pub os_name: String,
     pub retry_count: u32,
     pub retry_delay: u64,
+    pub auto_kick: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub auto_kick_ips: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control_socket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multidial: Option<Multidial>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe: Option<Probe>,
+    #[serde(skip_serializing_if = "Thresholds::is_empty")]
+    pub thresholds: Thresholds,
+    #[serde(skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    #[serde(skip_serializing_if = "Webhooks::is_empty")]
+    pub webhooks: Webhooks,
+    #[serde(skip_serializing_if = "Reload::is_empty")]
+    pub reload: Reload,
+    #[serde(skip_serializing_if = "StateFile::is_empty")]
+    pub state: StateFile,
     #[serde(skip)]
     pub cert_verification: CertVerification,
+    #[serde(skip)]
+    pub client_auth: Option<ClientAuth>,
 }
 
 impl Default for Config {
@@ -62,9 +126,14 @@ impl Default for Config {
             server: String::from("http://10.0.0.1"),
             server_ip: None,
             verify_cert: "system".into(),
+            client_cert: None,
+            client_key: None,
             users: vec![],
             strict_bind: false,
             enc: String::from("srun_bx1"),
+            enc_prefix: None,
+            enc_alphabet: None,
+            password_hash: PasswordHash::HmacMd5,
             n: 200,
             r#type: 1,
             acid: 1,
@@ -73,25 +142,37 @@ impl Default for Config {
             os_name: String::from("Linux"),
             retry_count: 10,
             retry_delay: 500,
+            auto_kick: false,
+            auto_kick_ips: vec![],
+            control_socket: None,
+            multidial: None,
+            probe: None,
+            thresholds: Thresholds::default(),
+            hooks: Hooks::default(),
+            webhooks: Webhooks::default(),
+            reload: Reload::default(),
+            state: StateFile::default(),
             #[cfg(feature = "tls")]
             cert_verification: CertVerification::System,
             #[cfg(not(feature = "tls"))]
             cert_verification: CertVerification::None,
+            client_auth: None,
         }
     }
 }
 
 impl Config {
-    pub fn from_json(json: &str) -> Self {
-        serde_json::from_str(json).unwrap()
+    pub fn from_cli(cli: Cli) -> Self {
+        Self::try_from_cli(cli).expect("Failed to read config file")
     }
 
-    pub fn from_cli(cli: Cli) -> Self {
+    /// Like [`Config::from_cli`], but reports an unreadable or malformed
+    /// config file instead of panicking.
+    pub fn try_from_cli(cli: Cli) -> Result<Self, Box<dyn Error>> {
         let mut config = Config::default();
         if let Some(config_file) = cli.config {
-            let config_content =
-                fs::read_to_string(config_file).expect("Failed to read config file");
-            config = Config::from_json(&config_content);
+            let config_content = fs::read_to_string(config_file)?;
+            config = serde_json::from_str(&config_content)?;
         }
 
         if let Some(server) = cli.server {
@@ -104,10 +185,13 @@ impl Config {
 
         if let (Some(username), Some(password)) = (cli.username, cli.password) {
             config.users = vec![User {
-                username: username,
-                password: password,
+                username,
+                password,
                 ip: cli.ip,
                 iface: cli.iface,
+                mac: None,
+                dhcp: false,
+                thresholds: None,
                 bind_addr: None,
             }];
         }
@@ -120,6 +204,18 @@ impl Config {
             config.enc = enc;
         }
 
+        if let Some(enc_prefix) = cli.enc_prefix {
+            config.enc_prefix = Some(enc_prefix);
+        }
+
+        if let Some(enc_alphabet) = cli.enc_alphabet {
+            config.enc_alphabet = Some(enc_alphabet);
+        }
+
+        if let Some(password_hash) = cli.password_hash {
+            config.password_hash = password_hash;
+        }
+
         if let Some(n) = cli.n {
             config.n = n;
         }
@@ -152,10 +248,38 @@ impl Config {
             config.retry_delay = retry_delay;
         }
 
+        if let Some(auto_kick) = cli.auto_kick {
+            config.auto_kick = auto_kick;
+        }
+
+        if let Some(auto_kick_ips) = cli.auto_kick_ips {
+            config.auto_kick_ips = auto_kick_ips;
+        }
+
+        if let Some(control_socket) = cli.control_socket {
+            config.control_socket = Some(control_socket);
+        }
+
+        if let Some(state_file) = cli.state_file {
+            config.state.path = Some(state_file);
+        }
+
+        if let Some(probe_url) = cli.probe_url {
+            config.probe = Some(Probe::new(probe_url));
+        }
+
         if let Some(verify_cert) = cli.verify_cert {
             config.verify_cert = verify_cert;
         }
 
+        if let Some(client_cert) = cli.client_cert {
+            config.client_cert = Some(client_cert);
+        }
+
+        if let Some(client_key) = cli.client_key {
+            config.client_key = Some(client_key);
+        }
+
         #[cfg(feature = "tls")]
         {
             config.cert_verification = match config.verify_cert.as_str() {
@@ -169,13 +293,30 @@ impl Config {
             config.cert_verification = CertVerification::None;
         }
 
-        config
+        if let (Some(cert), Some(key)) = (&config.client_cert, &config.client_key) {
+            config.client_auth = Some(ClientAuth {
+                cert: PathBuf::from(cert),
+                key: PathBuf::from(key),
+            });
+        }
+
+        Ok(config)
     }
 
     pub fn check(&mut self) -> Result<(), Box<dyn Error>> {
         if self.users.is_empty() {
             return Err(ConfigError::Validation("No users configured").into());
         }
+        if self.auto_kick && self.auto_kick_ips.is_empty() {
+            return Err(ConfigError::Validation("auto_kick requires auto_kick_ips").into());
+        }
+        if self
+            .auto_kick_ips
+            .iter()
+            .any(|ip| ip.parse::<IpAddr>().is_err())
+        {
+            return Err(ConfigError::Validation("Invalid IP address in auto_kick_ips").into());
+        }
         for user in &self.users {
             if user.username.is_empty() {
                 return Err(ConfigError::Validation("Username cannot be empty").into());
@@ -183,28 +324,43 @@ impl Config {
             if user.password.is_empty() {
                 return Err(ConfigError::Validation("Password cannot be empty").into());
             }
+            if user.dhcp && user.iface.is_none() {
+                return Err(ConfigError::Validation("dhcp requires iface").into());
+            }
         }
+        self.check_client_auth()?;
+        Encoding::new(
+            &self.enc,
+            self.enc_prefix.as_deref(),
+            self.enc_alphabet.as_deref(),
+        )?;
+        let managed = match &self.multidial {
+            Some(multidial) => multidial.assign(&mut self.users)?,
+            None => Vec::new(),
+        };
         let interfaces = if_addrs::get_if_addrs().expect("Failed to get network interfaces");
         let mut interfaces_iter = interfaces.clone().into_iter();
 
-        for user in &mut self.users {
+        for (i, user) in self.users.iter_mut().enumerate() {
+            if let Some(mac) = &user.mac {
+                if mac::parse(mac).is_none() {
+                    return Err(ConfigError::Validation("Invalid MAC address").into());
+                }
+                if user.iface.is_none() {
+                    return Err(ConfigError::Validation("mac requires iface or multidial").into());
+                }
+            }
             if let Some(user_iface) = &user.iface {
-                let iface = interfaces_iter
-                    .find(|iface_info| {
-                        iface_info.name == *user_iface
-                            && matches!(iface_info.addr, if_addrs::IfAddr::V4(_))
-                    })
-                    .ok_or_else(|| {
-                        ConfigError::Validation("Network interface not found or no IPv4 address")
-                    })?;
-                let ip = match iface.addr {
-                    if_addrs::IfAddr::V4(v4_addr) => v4_addr.ip.to_string(),
-                    if_addrs::IfAddr::V6(_) => {
+                let ip = match iface_ipv4(user_iface) {
+                    Ok(ip) => ip.to_string(),
+                    // 托管的 macvlan 只在 daemon 或 exporter 运行时存在
+                    Err(_) if managed.contains(&i) => {
                         return Err(ConfigError::Validation(
-                            "Interface does not have IPv4 address",
+                            "Managed macvlan not found or without IPv4 address, is srun daemon or exporter running?",
                         )
                         .into());
                     }
+                    Err(e) => return Err(e),
                 };
                 if let Some(user_ip) = &user.ip
                     && user_ip != &ip
@@ -225,9 +381,9 @@ impl Config {
                     .find(|iface_info| {
                         iface_info.ip().is_ipv4() && iface_info.ip().to_string() == *user_ip
                     })
-                    .ok_or_else(|| {
-                        ConfigError::Validation("IP address not found on any interface")
-                    })?;
+                    .ok_or(ConfigError::Validation(
+                        "IP address not found on any interface",
+                    ))?;
                 user.bind_addr = Some(ip);
             }
             if self.strict_bind && user.bind_addr.is_none() {
@@ -240,9 +396,50 @@ impl Config {
         Ok(())
     }
 
+    /// The user's own thresholds, or the global ones.
+    /// The thresholds of `user`: each limit it sets itself, the global one
+    /// otherwise.
+    pub fn thresholds_for(&self, user: &User) -> Thresholds {
+        match &user.thresholds {
+            Some(thresholds) => thresholds.or(&self.thresholds),
+            None => self.thresholds.clone(),
+        }
+    }
+
+    fn check_client_auth(&self) -> Result<(), Box<dyn Error>> {
+        #[cfg(not(feature = "tls"))]
+        if self.client_cert.is_some() || self.client_key.is_some() {
+            return Err(ConfigError::Validation(
+                "client_cert and client_key require a build with the tls feature",
+            )
+            .into());
+        }
+        if self.client_cert.is_some() != self.client_key.is_some() {
+            return Err(ConfigError::Validation(
+                "client_cert and client_key must be specified together",
+            )
+            .into());
+        }
+        let Some(client_auth) = &self.client_auth else {
+            return Ok(());
+        };
+        if !client_auth.cert.is_file() {
+            return Err(ConfigError::Validation("Client certificate file not found").into());
+        }
+        if !client_auth.key.is_file() {
+            return Err(ConfigError::Validation("Client key file not found").into());
+        }
+        #[cfg(feature = "tls")]
+        client_auth.load()?;
+        Ok(())
+    }
+
     pub fn generate_example_config(path: PathBuf) -> Result<(), Box<dyn Error>> {
-        let mut example_config = Config::default();
-        example_config.server_ip = Some(String::from("10.0.0.1"));
+        let mut example_config = Config {
+            server_ip: Some(String::from("10.0.0.1")),
+            probe: Some(Probe::default()),
+            ..Default::default()
+        };
         #[cfg(feature = "tls")]
         {
             example_config.verify_cert = String::from("system");
@@ -256,6 +453,9 @@ impl Config {
             password: String::from("your_password"),
             ip: Some(String::from("your_ipv4_address")),
             iface: None,
+            mac: None,
+            dhcp: false,
+            thresholds: None,
             bind_addr: None,
         });
         example_config.users.push(User {
@@ -263,6 +463,12 @@ impl Config {
             password: String::from("your_password"),
             ip: None,
             iface: Some(String::from("your_interface_name")),
+            mac: None,
+            dhcp: false,
+            thresholds: Some(Thresholds {
+                min_balance: Some(5.0),
+                ..Default::default()
+            }),
             bind_addr: None,
         });
         let json = serde_json::to_string_pretty(&example_config)?;
@@ -270,3 +476,16 @@ impl Config {
         Ok(())
     }
 }
+
+/// The IPv4 address currently assigned to the network interface `name`.
+pub fn iface_ipv4(name: &str) -> Result<Ipv4Addr, Box<dyn Error>> {
+    if_addrs::get_if_addrs()?
+        .into_iter()
+        .find_map(|iface| match iface.addr {
+            if_addrs::IfAddr::V4(v4_addr) if iface.name == name => Some(v4_addr.ip),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            ConfigError::Validation("Network interface not found or no IPv4 address").into()
+        })
+}
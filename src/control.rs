@@ -0,0 +1,196 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::mpsc::Sender;
+
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::sync::mpsc;
+#[cfg(unix)]
+use std::thread;
+
+#[cfg(unix)]
+use crate::debug;
+
+/// Commands accepted on the control socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Show the state of the users
+    Status,
+    /// Log in now, resuming paused users
+    Login,
+    /// Log out now and pause the users
+    Logout,
+    /// Re-read the configuration
+    Reload,
+    /// Stop logging in the users automatically
+    Pause,
+    /// Undo pause
+    Resume,
+}
+
+/// One JSON line sent by `srun ctl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub action: Action,
+    /// Username to act on, all users if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// State of one user as reported by `status`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserStatus {
+    pub user: String,
+    pub online: bool,
+    pub paused: bool,
+    pub ip: Option<String>,
+    pub last_refresh: u64,
+    pub sum_bytes: Option<u64>,
+    pub user_balance: Option<f64>,
+}
+
+/// One JSON line sent back by the daemon.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<UserStatus>,
+}
+
+impl Response {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            message: message.into(),
+            users: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            message: message.into(),
+            users: vec![],
+        }
+    }
+}
+
+/// A request handed to the daemon loop, answered through `reply`.
+pub struct Message {
+    pub request: Request,
+    pub reply: Sender<Response>,
+}
+
+/// Listens on a Unix domain socket and forwards requests to the daemon.
+#[cfg(unix)]
+pub struct ControlServer {
+    listener: UnixListener,
+}
+
+#[cfg(unix)]
+impl ControlServer {
+    /// Binds `path`, replacing a stale socket left by a previous run.
+    pub fn bind(path: &str) -> Result<Self, Box<dyn Error>> {
+        if Path::new(path).exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(format!("Control socket {} is in use", path).into());
+            }
+            std::fs::remove_file(path)?;
+        }
+        Ok(Self {
+            listener: UnixListener::bind(path)?,
+        })
+    }
+
+    /// Serves connections on a background thread.
+    pub fn spawn(self, daemon: Sender<Message>) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let daemon = daemon.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, &daemon) {
+                        debug!("Control connection closed: {}", e);
+                    }
+                });
+            }
+        })
+    }
+}
+
+#[cfg(unix)]
+fn serve(stream: UnixStream, daemon: &Sender<Message>) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (reply, receiver) = mpsc::channel();
+                daemon.send(Message { request, reply })?;
+                receiver.recv()?
+            }
+            Err(e) => Response::error(format!("Invalid request: {}", e)),
+        };
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Sends one request to the daemon listening on `path`.
+#[cfg(unix)]
+pub fn send(path: &str, request: &Request) -> Result<Response, Box<dyn Error>> {
+    let stream = UnixStream::connect(path)
+        .map_err(|e| format!("Cannot connect to control socket {}: {}", path, e))?;
+    let mut writer = stream.try_clone()?;
+    writeln!(writer, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(not(unix))]
+pub fn send(_path: &str, _request: &Request) -> Result<Response, Box<dyn Error>> {
+    Err("The control socket is only supported on Unix".into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("srun-ctl-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        ControlServer::bind(path)?.spawn(sender);
+        assert!(ControlServer::bind(path).is_err());
+        thread::spawn(move || {
+            for Message { request, reply } in receiver {
+                let _ = reply.send(Response::ok(format!(
+                    "{:?} {:?}",
+                    request.action, request.user
+                )));
+            }
+        });
+
+        let request = Request {
+            action: Action::Pause,
+            user: Some(String::from("alice")),
+        };
+        let response = send(path, &request)?;
+        let _ = std::fs::remove_file(path);
+        assert!(response.ok);
+        assert_eq!(response.message, "Pause Some(\"alice\")");
+        Ok(())
+    }
+}
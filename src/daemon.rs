@@ -0,0 +1,896 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::alert::Alert;
+use crate::config::{self, Config, User};
+use crate::control::{Action, Message, Request, Response, UserStatus};
+use crate::hooks::{Event, EventInfo};
+use crate::metrics::Metrics;
+use crate::notify::Dispatcher;
+use crate::srun::{InfoResponse, SrunClient};
+use crate::{debug, info, warn};
+
+/// Re-reads and validates the configuration for `reload`.
+pub type Loader = Box<dyn Fn() -> Result<Config, Box<dyn Error>>>;
+
+/// How often pending reloads are checked while waiting for the next refresh.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Set by the SIGHUP handler installed by [`listen_signals`].
+static SIGHUP: AtomicBool = AtomicBool::new(false);
+/// Set by the SIGINT and SIGTERM handler installed by [`listen_signals`].
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Makes SIGHUP reload the configuration, and SIGINT or SIGTERM return from
+/// [`Daemon::run`] so that interfaces can be cleaned up. A second SIGINT or
+/// SIGTERM terminates the process right away.
+#[cfg(unix)]
+pub fn listen_signals() {
+    extern "C" fn reload(_: libc::c_int) {
+        SIGHUP.store(true, Ordering::Relaxed);
+    }
+    extern "C" fn stop(signal: libc::c_int) {
+        STOP.store(true, Ordering::Relaxed);
+        // SAFETY: signal() is async-signal-safe
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+    let reload: extern "C" fn(libc::c_int) = reload;
+    let stop: extern "C" fn(libc::c_int) = stop;
+    // SAFETY: the handlers only store to atomics and reset themselves, which
+    // is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGHUP, reload as libc::sighandler_t);
+        libc::signal(libc::SIGINT, stop as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, stop as libc::sighandler_t);
+    }
+}
+
+/// When and how the daemon applies a changed configuration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Reload {
+    /// Also reload when the config file is modified, not only on SIGHUP
+    pub watch: bool,
+    /// Log out users removed from the configuration
+    pub logout_removed: bool,
+}
+
+impl Reload {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Long-running mode: queries `rad_user_info` for every user on an interval,
+/// records the results in [`Metrics`] and optionally logs in users found
+/// offline. Between refreshes it serves requests from the control socket.
+pub struct Daemon {
+    config: Config,
+    interval: Duration,
+    keep_online: bool,
+    metrics: Arc<Mutex<Metrics>>,
+    /// Thresholds currently crossed per user, reported once until cleared
+    alerts: HashMap<String, Vec<Alert>>,
+    seen: HashMap<String, Seen>,
+    events: Dispatcher,
+    /// Users not logged in automatically
+    paused: HashSet<String>,
+    control: Option<Receiver<Message>>,
+    loader: Option<Loader>,
+    /// Config file watched for changes, with its last modification time
+    watch: Option<(PathBuf, Option<SystemTime>)>,
+    /// Names of interfaces whose address changed
+    address_changes: Option<Receiver<String>>,
+}
+
+/// Last observed state of a user, to detect changes between refreshes.
+#[derive(Debug, Clone, Default)]
+struct Seen {
+    online: Option<bool>,
+    ip: Option<String>,
+}
+
+impl Daemon {
+    pub fn new(config: Config, interval: Duration, keep_online: bool) -> Self {
+        Self {
+            events: Dispatcher::new(&config),
+            config,
+            interval,
+            keep_online,
+            metrics: Arc::new(Mutex::new(Metrics::default())),
+            alerts: HashMap::new(),
+            seen: HashMap::new(),
+            paused: HashSet::new(),
+            control: None,
+            loader: None,
+            watch: None,
+            address_changes: None,
+        }
+    }
+
+    /// Returns the sender for control requests, handled between refreshes.
+    pub fn control(&mut self) -> Sender<Message> {
+        let (sender, receiver) = mpsc::channel();
+        self.control = Some(receiver);
+        sender
+    }
+
+    pub fn set_loader(&mut self, loader: Loader) {
+        self.loader = Some(loader);
+    }
+
+    /// Reloads the configuration whenever `path` is modified.
+    pub fn watch(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        let modified = modified(&path);
+        self.watch = Some((path, modified));
+    }
+
+    /// Refreshes the users of an interface as soon as its name is received,
+    /// instead of waiting for the next interval.
+    pub fn set_address_changes(&mut self, changes: Receiver<String>) {
+        self.address_changes = Some(changes);
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn metrics(&self) -> Arc<Mutex<Metrics>> {
+        self.metrics.clone()
+    }
+
+    /// Refreshes on the interval until SIGINT or SIGTERM is received.
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        while !STOP.load(Ordering::Relaxed) {
+            self.refresh();
+            self.wait();
+        }
+        info!("Stopping");
+        Ok(())
+    }
+
+    /// Sleeps for the interval, handling control requests and reloads
+    /// meanwhile.
+    fn wait(&mut self) {
+        let deadline = Instant::now() + self.interval;
+        while !STOP.load(Ordering::Relaxed) {
+            if self.reload_requested() {
+                self.reload();
+            }
+            self.handle_address_changes();
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return;
+            }
+            let timeout = timeout.min(POLL_INTERVAL);
+            let Some(control) = &self.control else {
+                thread::sleep(timeout);
+                continue;
+            };
+            match control.recv_timeout(timeout) {
+                Ok(Message { request, reply }) => {
+                    let response = self.handle(request);
+                    let _ = reply.send(response);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => self.control = None,
+            }
+        }
+    }
+
+    fn handle_address_changes(&mut self) {
+        let Some(changes) = &self.address_changes else {
+            return;
+        };
+        let mut ifaces = HashSet::new();
+        loop {
+            match changes.try_recv() {
+                Ok(iface) => {
+                    debug!("Address of interface {} changed", iface);
+                    ifaces.insert(iface);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.address_changes = None;
+                    break;
+                }
+            }
+        }
+        let users: Vec<User> = self
+            .config
+            .users
+            .iter()
+            .filter(|user| {
+                user.iface
+                    .as_ref()
+                    .is_some_and(|iface| ifaces.contains(iface))
+            })
+            .cloned()
+            .collect();
+        for user in users {
+            self.refresh_one(&user);
+        }
+    }
+
+    /// Whether SIGHUP was received or the watched config file changed.
+    fn reload_requested(&mut self) -> bool {
+        let mut requested = SIGHUP.swap(false, Ordering::Relaxed);
+        if requested {
+            info!("Received SIGHUP, reloading configuration");
+        }
+        if let Some((path, last)) = &mut self.watch {
+            let modified = modified(path);
+            if modified != *last {
+                *last = modified;
+                if !requested {
+                    info!("{} changed, reloading configuration", path.display());
+                }
+                requested = true;
+            }
+        }
+        requested
+    }
+
+    pub fn handle(&mut self, request: Request) -> Response {
+        debug!("Control request {:?}", request);
+        if request.action == Action::Reload {
+            return self.reload();
+        }
+        let users: Vec<User> = self
+            .config
+            .users
+            .iter()
+            .filter(|user| {
+                request
+                    .user
+                    .as_ref()
+                    .is_none_or(|name| *name == user.username)
+            })
+            .cloned()
+            .collect();
+        if users.is_empty() {
+            return match &request.user {
+                Some(name) => Response::error(format!("Unknown user {}", name)),
+                None => Response::error("No users configured"),
+            };
+        }
+        let names: Vec<&str> = users.iter().map(|user| user.username.as_str()).collect();
+        let names = names.join(", ");
+
+        let mut failed = Vec::new();
+        match request.action {
+            Action::Status => {
+                return Response {
+                    ok: true,
+                    message: String::new(),
+                    users: users.iter().map(|user| self.user_status(user)).collect(),
+                };
+            }
+            Action::Pause => {
+                self.paused
+                    .extend(users.into_iter().map(|user| user.username));
+                return Response::ok(format!("Paused {}", names));
+            }
+            Action::Resume => {
+                for user in &users {
+                    self.paused.remove(&user.username);
+                }
+                return Response::ok(format!("Resumed {}", names));
+            }
+            Action::Login => {
+                for user in &users {
+                    self.paused.remove(&user.username);
+                    if let Err(e) = self.login_now(user) {
+                        failed.push(format!("{}: {}", user.username, e));
+                    }
+                }
+            }
+            Action::Logout => {
+                for user in &users {
+                    self.paused.insert(user.username.clone());
+                    if let Err(e) = self.logout_now(user) {
+                        failed.push(format!("{}: {}", user.username, e));
+                    }
+                }
+            }
+            Action::Reload => unreachable!(),
+        }
+        if failed.is_empty() {
+            Response::ok(format!("Done for {}", names))
+        } else {
+            Response::error(failed.join("; "))
+        }
+    }
+
+    fn user_status(&self, user: &User) -> UserStatus {
+        let metrics = self.metrics.lock().unwrap();
+        let entry = metrics
+            .users
+            .get(&user.username)
+            .cloned()
+            .unwrap_or_default();
+        UserStatus {
+            user: user.username.clone(),
+            online: entry.online,
+            paused: self.paused.contains(&user.username),
+            ip: self
+                .seen
+                .get(&user.username)
+                .and_then(|seen| seen.ip.clone()),
+            last_refresh: entry.last_refresh,
+            sum_bytes: entry.sum_bytes,
+            user_balance: entry.user_balance,
+        }
+    }
+
+    /// Replaces the configuration by the loader's, keeping the current one
+    /// if the new one is invalid. Only users whose credentials or address
+    /// changed are logged in or out; other settings apply in place.
+    fn reload(&mut self) -> Response {
+        let Some(loader) = &self.loader else {
+            return Response::error("Reload is not available");
+        };
+        let mut config = match loader() {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Invalid configuration, keeping the old one: {}", e);
+                return Response::error(format!(
+                    "Invalid configuration, keeping the old one: {}",
+                    e
+                ));
+            }
+        };
+        let removed: Vec<User> = self
+            .config
+            .users
+            .iter()
+            .filter(|user| !config.users.iter().any(|new| new.same_login(user)))
+            .cloned()
+            .collect();
+        let mut added = Vec::new();
+        for user in &mut config.users {
+            match self.config.users.iter().find(|old| old.same_login(user)) {
+                // 保留运行时解析的地址，其余设置直接替换
+                Some(old) => {
+                    user.ip.clone_from(&old.ip);
+                    user.bind_addr = old.bind_addr;
+                }
+                None => added.push(user.clone()),
+            }
+        }
+
+        for user in &removed {
+            // 注销仍使用旧配置
+            if config.reload.logout_removed {
+                info!("User {} was removed, logging out", user.username);
+                if let Err(e) = self.logout_now(user) {
+                    warn!("Logging out user {} failed: {}", user.username, e);
+                }
+            }
+            if !config.users.iter().any(|new| new.username == user.username) {
+                self.forget(&user.username);
+            }
+        }
+        self.events = Dispatcher::new(&config);
+        let old = mem::replace(&mut self.config, config);
+        for user in &added {
+            if self.keep_online && !self.paused.contains(&user.username) {
+                info!("User {} was added, logging in", user.username);
+                if let Err(e) = self.login_added(user) {
+                    warn!("Logging in user {} failed: {}", user.username, e);
+                }
+            }
+        }
+        if old.control_socket != self.config.control_socket {
+            warn!("Changing control_socket requires a restart");
+        }
+
+        let message = format!(
+            "Configuration reloaded, {} users added, {} removed",
+            added.len(),
+            removed.len()
+        );
+        info!("{}", message);
+        Response::ok(message)
+    }
+
+    /// Drops everything known about a user no longer configured.
+    fn forget(&mut self, username: &str) {
+        self.metrics.lock().unwrap().users.remove(username);
+        self.seen.remove(username);
+        self.alerts.remove(username);
+        self.paused.remove(username);
+    }
+
+    fn client(&self, user: &User) -> Result<SrunClient, Box<dyn Error>> {
+        let mut client = SrunClient::new(&self.config, user.clone())?;
+        if user.bind_addr.is_none() {
+            // 未指定 IP 时由 rad_user_info 得到
+            client.check_status()?;
+        }
+        Ok(client)
+    }
+
+    fn login_now(&mut self, user: &User) -> Result<(), Box<dyn Error>> {
+        let mut client = self.client(user)?;
+        self.login_user(&mut client, user, None)
+    }
+
+    /// Logs in a newly configured user, unless already online.
+    fn login_added(&mut self, user: &User) -> Result<(), Box<dyn Error>> {
+        let mut client = SrunClient::new(&self.config, user.clone())?;
+        let (online, online_info) = client.check_status()?;
+        if online {
+            return Ok(());
+        }
+        self.login_user(&mut client, user, online_info.user_mac)
+    }
+
+    fn logout_now(&mut self, user: &User) -> Result<(), Box<dyn Error>> {
+        let mut client = self.client(user)?;
+        client.logout()?;
+        let event = EventInfo::from_client(user, &client, &Ok(()));
+        self.events.fire(Event::Logout, &event);
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics
+            .users
+            .entry(user.username.clone())
+            .or_default()
+            .online = false;
+        self.seen.entry(user.username.clone()).or_default().online = Some(false);
+        Ok(())
+    }
+
+    /// Refreshes all users once. Errors are logged and do not stop the loop.
+    pub fn refresh(&mut self) {
+        for user in self.config.users.clone() {
+            self.refresh_one(&user);
+        }
+    }
+
+    fn refresh_one(&mut self, user: &User) {
+        let result = self.resolve(user).and_then(|user| self.refresh_user(&user));
+        if let Err(e) = result {
+            warn!("Refreshing user {} failed: {}", user.username, e);
+        }
+    }
+
+    /// Re-reads the address of the user's interface, which may have changed
+    /// since the configuration was checked, e.g. after a DHCP renewal.
+    fn resolve(&mut self, user: &User) -> Result<User, Box<dyn Error>> {
+        let Some(iface) = &user.iface else {
+            return Ok(user.clone());
+        };
+        let ip = config::iface_ipv4(iface)?;
+        if user.bind_addr == Some(ip.into()) {
+            return Ok(user.clone());
+        }
+        info!(
+            "Address of interface {} for user {} changed from {} to {}",
+            iface,
+            user.username,
+            user.ip.as_deref().unwrap_or("none"),
+            ip
+        );
+        let mut user = user.clone();
+        user.ip = Some(ip.to_string());
+        user.bind_addr = Some(ip.into());
+        if let Some(configured) = self
+            .config
+            .users
+            .iter_mut()
+            .find(|c| c.username == user.username)
+        {
+            *configured = user.clone();
+        }
+        Ok(user)
+    }
+
+    fn refresh_user(&mut self, user: &User) -> Result<(), Box<dyn Error>> {
+        let mut client = SrunClient::new(&self.config, user.clone())?;
+        let status = client.check_status();
+        let online = {
+            let mut metrics = self.metrics.lock().unwrap();
+            let entry = metrics.users.entry(user.username.clone()).or_default();
+            entry.last_refresh = now();
+            match &status {
+                Ok((online, online_info)) => entry.update(*online, online_info),
+                Err(_) => entry.up = false,
+            }
+            entry.online
+        };
+        let (_, online_info) = status?;
+        self.check_thresholds(user, &online_info);
+        if online {
+            self.events.sample(&user.username, &online_info);
+        }
+
+        let mut event = EventInfo::new(&user.username, Some(online_info.online_ip.clone()));
+        event.mac = online_info.user_mac.clone();
+        let seen = self.seen.entry(user.username.clone()).or_default();
+        let was_online = seen.online.replace(online);
+        if online {
+            self.observe_ip(&event);
+        } else if was_online == Some(true) {
+            info!("User {} is offline", user.username);
+            self.events.fire(Event::OfflineDetected, &event);
+        }
+
+        if !online && self.keep_online && !self.paused.contains(&user.username) {
+            info!("User {} is offline, logging in", user.username);
+            self.login_user(&mut client, user, online_info.user_mac)?;
+        }
+        Ok(())
+    }
+
+    fn login_user(
+        &mut self,
+        client: &mut SrunClient,
+        user: &User,
+        mac: Option<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        let result = client.login();
+        let mut event = EventInfo::from_client(user, client, &result);
+        event.mac = mac;
+        {
+            let mut metrics = self.metrics.lock().unwrap();
+            let entry = metrics.users.entry(user.username.clone()).or_default();
+            entry.login_attempts += 1;
+            entry.login_latency = Some(start.elapsed());
+            match &result {
+                Ok(()) => entry.online = true,
+                Err(_) => entry.login_failures += 1,
+            }
+        }
+        if result.is_ok() {
+            self.events.fire(Event::Login, &event);
+            self.seen.entry(user.username.clone()).or_default().online = Some(true);
+            self.observe_ip(&event);
+        } else {
+            self.events.fire(Event::LoginFailed, &event);
+        }
+        result
+    }
+
+    /// Records the online address of a user, running `on_ip_changed` if it
+    /// differs from the last one.
+    fn observe_ip(&mut self, event: &EventInfo) {
+        let Some(ip) = &event.ip else { return };
+        let seen = self.seen.entry(event.user.clone()).or_default();
+        let old_ip = seen.ip.replace(ip.clone());
+        if let Some(old_ip) = old_ip
+            && old_ip != *ip
+        {
+            info!(
+                "IP of user {} changed from {} to {}",
+                event.user, old_ip, ip
+            );
+            let event = EventInfo {
+                old_ip: Some(old_ip),
+                ..event.clone()
+            };
+            self.events.fire(Event::IpChanged, &event);
+        }
+    }
+
+    fn check_thresholds(&mut self, user: &User, online_info: &InfoResponse) {
+        let thresholds = self.config.thresholds_for(user);
+        let alerts = thresholds.check(online_info);
+        let previous = self.alerts.remove(&user.username).unwrap_or_default();
+        let new: Vec<Alert> = alerts
+            .iter()
+            .filter(|alert| !previous.iter().any(|p| p.kind == alert.kind))
+            .cloned()
+            .collect();
+        thresholds.report(
+            &user.username,
+            &new,
+            Duration::from_millis(self.config.hooks.timeout),
+        );
+        if !alerts.is_empty() {
+            self.alerts.insert(user.username.clone(), alerts);
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::Thresholds;
+    use crate::mock::{MockOptions, MockServer};
+
+    fn user(username: &str, password: &str) -> User {
+        User {
+            username: username.into(),
+            password: password.into(),
+            ip: Some(String::from("127.0.0.1")),
+            iface: None,
+            mac: None,
+            dhcp: false,
+            thresholds: None,
+            bind_addr: Some("127.0.0.1".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_refresh_keep_online() -> Result<(), Box<dyn Error>> {
+        let mut config = Config {
+            retry_count: 1,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        let options = MockOptions {
+            accounts: vec![(String::from("alice"), String::from("secret"))],
+            ..Default::default()
+        };
+        let server = MockServer::bind("127.0.0.1:0", &config, options)?;
+        config.server = format!("http://{}", server.local_addr()?);
+        server.spawn();
+        config.users = vec![user("alice", "wrong")];
+        // The mock reports a balance of 10
+        config.thresholds.min_balance = Some(20.0);
+
+        let mut daemon = Daemon::new(config, Duration::ZERO, true);
+        daemon.refresh();
+        daemon.config.users = vec![user("alice", "secret")];
+        daemon.refresh();
+        daemon.refresh();
+        let metrics = daemon.metrics();
+        let metrics = metrics.lock().unwrap();
+
+        let alice = &metrics.users["alice"];
+        assert!(alice.up && alice.online);
+        assert_eq!((alice.login_attempts, alice.login_failures), (2, 1));
+        assert!(alice.bytes_in.is_some());
+        assert!(alice.login_latency.is_some());
+        assert_eq!(daemon.alerts["alice"][0].kind, "balance");
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle() -> Result<(), Box<dyn Error>> {
+        let mut config = Config {
+            retry_count: 1,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        let options = MockOptions {
+            accounts: vec![(String::from("alice"), String::from("secret"))],
+            ..Default::default()
+        };
+        let server = MockServer::bind("127.0.0.1:0", &config, options)?;
+        config.server = format!("http://{}", server.local_addr()?);
+        server.spawn();
+        config.users = vec![user("alice", "secret")];
+        let request = |action, user: Option<&str>| Request {
+            action,
+            user: user.map(String::from),
+        };
+
+        let mut daemon = Daemon::new(config, Duration::ZERO, true);
+        assert!(daemon.handle(request(Action::Pause, None)).ok);
+        daemon.refresh();
+        let status = daemon.handle(request(Action::Status, Some("alice")));
+        assert!(status.users[0].paused && !status.users[0].online);
+
+        assert!(daemon.handle(request(Action::Login, Some("alice"))).ok);
+        daemon.refresh();
+        let status = daemon.handle(request(Action::Status, None));
+        assert!(!status.users[0].paused && status.users[0].online);
+
+        assert!(daemon.handle(request(Action::Logout, None)).ok);
+        daemon.refresh();
+        let status = daemon.handle(request(Action::Status, None));
+        assert!(status.users[0].paused && !status.users[0].online);
+
+        assert!(!daemon.handle(request(Action::Resume, Some("bob"))).ok);
+        assert!(!daemon.handle(request(Action::Reload, None)).ok);
+        daemon.set_loader(Box::new(|| Err("bad config".into())));
+        let response = daemon.handle(request(Action::Reload, None));
+        assert!(!response.ok && response.message.contains("bad config"));
+        assert_eq!(daemon.config.users[0].username, "alice");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload() -> Result<(), Box<dyn Error>> {
+        let mut config = Config {
+            retry_count: 1,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        let options = MockOptions {
+            accounts: vec![
+                (String::from("alice"), String::from("secret")),
+                (String::from("bob"), String::from("hunter2")),
+            ],
+            ..Default::default()
+        };
+        let server = MockServer::bind("127.0.0.1:0", &config, options)?;
+        config.server = format!("http://{}", server.local_addr()?);
+        server.spawn();
+        config.reload.logout_removed = true;
+        let alice = user("alice", "secret");
+        let bob = User {
+            ip: Some(String::from("127.0.0.2")),
+            bind_addr: Some("127.0.0.2".parse().unwrap()),
+            ..user("bob", "hunter2")
+        };
+        config.users = vec![alice.clone()];
+
+        let next = Arc::new(Mutex::new(Ok(config.clone())));
+        let mut daemon = Daemon::new(config.clone(), Duration::ZERO, true);
+        let loader = next.clone();
+        daemon.set_loader(Box::new(move || {
+            mem::replace(
+                &mut *loader.lock().unwrap(),
+                Err(String::from("bad config")),
+            )
+            .map_err(Into::into)
+        }));
+        daemon.refresh();
+
+        config.users = vec![alice.clone(), bob.clone()];
+        *next.lock().unwrap() = Ok(config.clone());
+        let response = daemon.handle(Request {
+            action: Action::Reload,
+            user: None,
+        });
+        assert_eq!(
+            response.message,
+            "Configuration reloaded, 1 users added, 0 removed"
+        );
+        {
+            let metrics = daemon.metrics.lock().unwrap();
+            assert_eq!(metrics.users["alice"].login_attempts, 1);
+            assert!(metrics.users["bob"].online);
+        }
+
+        config.users = vec![bob.clone()];
+        *next.lock().unwrap() = Ok(config.clone());
+        assert!(daemon.reload().ok);
+        assert!(!SrunClient::new(&config, alice)?.check_status()?.0);
+        {
+            let metrics = daemon.metrics.lock().unwrap();
+            assert!(!metrics.users.contains_key("alice"));
+            assert_eq!(metrics.users["bob"].login_attempts, 1);
+        }
+
+        let thresholds = Thresholds {
+            min_balance: Some(1.0),
+            ..Default::default()
+        };
+        config.users = vec![User {
+            thresholds: Some(thresholds.clone()),
+            ..bob.clone()
+        }];
+        *next.lock().unwrap() = Ok(config.clone());
+        assert_eq!(
+            daemon.reload().message,
+            "Configuration reloaded, 0 users added, 0 removed"
+        );
+        assert_eq!(daemon.config.users[0].thresholds, Some(thresholds));
+        {
+            let metrics = daemon.metrics.lock().unwrap();
+            assert!(metrics.users["bob"].online);
+            assert_eq!(metrics.users["bob"].login_attempts, 1);
+        }
+
+        let response = daemon.reload();
+        assert!(!response.ok && response.message.contains("bad config"));
+        assert_eq!(daemon.config.users.len(), 1);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_address_changes() -> Result<(), Box<dyn Error>> {
+        let mut config = Config {
+            retry_count: 1,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        let options = MockOptions {
+            accounts: vec![(String::from("alice"), String::from("secret"))],
+            ..Default::default()
+        };
+        let server = MockServer::bind("127.0.0.1:0", &config, options)?;
+        config.server = format!("http://{}", server.local_addr()?);
+        server.spawn();
+        // Resolved to another address before the "renewal"
+        config.users = vec![User {
+            iface: Some(String::from("lo")),
+            ip: Some(String::from("127.0.0.2")),
+            bind_addr: Some("127.0.0.2".parse().unwrap()),
+            ..user("alice", "secret")
+        }];
+
+        let (sender, receiver) = mpsc::channel();
+        let mut daemon = Daemon::new(config, Duration::ZERO, true);
+        daemon.set_address_changes(receiver);
+        sender.send(String::from("eth9"))?;
+        daemon.handle_address_changes();
+        assert!(daemon.metrics.lock().unwrap().users.is_empty());
+
+        sender.send(String::from("lo"))?;
+        daemon.handle_address_changes();
+        let alice = &daemon.config.users[0];
+        assert_eq!(alice.ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(alice.bind_addr, Some("127.0.0.1".parse()?));
+        let metrics = daemon.metrics.lock().unwrap();
+        assert!(metrics.users["alice"].online);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refresh_hooks() -> Result<(), Box<dyn Error>> {
+        let mut config = Config {
+            retry_count: 1,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        let options = MockOptions {
+            accounts: vec![(String::from("alice"), String::from("secret"))],
+            ..Default::default()
+        };
+        let server = MockServer::bind("127.0.0.1:0", &config, options)?;
+        config.server = format!("http://{}", server.local_addr()?);
+        server.spawn();
+        config.users = vec![user("alice", "secret")];
+        let out = std::env::temp_dir().join(format!("srun-daemon-{}", std::process::id()));
+        let hook = format!("echo \"$SRUN_EVENT $SRUN_IP\" >> {}", out.display());
+        config.hooks.on_login = Some(hook.clone());
+        config.hooks.on_offline_detected = Some(hook.clone());
+        config.hooks.on_ip_changed = Some(hook);
+
+        // Never online before the first refresh, so only the logout counts
+        // as a drop
+        let mut daemon = Daemon::new(config.clone(), Duration::ZERO, true);
+        daemon.refresh();
+        daemon.refresh();
+        SrunClient::new(&config, user("alice", "secret"))?.logout()?;
+        daemon.refresh();
+        thread::sleep(Duration::from_millis(500));
+
+        let mut events: Vec<String> = std::fs::read_to_string(&out)?
+            .lines()
+            .map(String::from)
+            .collect();
+        let _ = std::fs::remove_file(&out);
+        events.sort();
+        assert_eq!(
+            events,
+            [
+                "login 127.0.0.1",
+                "login 127.0.0.1",
+                "offline_detected 127.0.0.1",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+
+/// A Unix timestamp broken down into calendar fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// In the local time zone of the system, UTC where it is not known.
+    #[cfg(unix)]
+    pub fn local(timestamp: u64) -> Self {
+        let time = timestamp as libc::time_t;
+        // SAFETY: localtime_r only writes to tm, which is plain data
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+            return Self::utc(timestamp);
+        }
+        Self {
+            year: tm.tm_year + 1900,
+            month: (tm.tm_mon + 1) as u32,
+            day: tm.tm_mday as u32,
+            hour: tm.tm_hour as u32,
+            minute: tm.tm_min as u32,
+            second: tm.tm_sec as u32,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn local(timestamp: u64) -> Self {
+        Self::utc(timestamp)
+    }
+
+    pub fn utc(timestamp: u64) -> Self {
+        let days = (timestamp / 86400) as i64;
+        let seconds = (timestamp % 86400) as u32;
+        // Howard Hinnant's civil_from_days
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + i64::from(month <= 2)) as i32;
+        Self {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+        }
+    }
+
+    /// Midnight of a `2026-10-01` date.
+    pub fn parse_date(date: &str) -> Option<Self> {
+        let mut fields = date.trim().splitn(3, '-');
+        let year = fields.next()?.parse().ok()?;
+        let month = fields.next()?.parse().ok()?;
+        let day = fields.next()?.parse().ok()?;
+        let days_in_month = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            _ => return None,
+        };
+        if !(1..=days_in_month).contains(&day) {
+            return None;
+        }
+        Some(Self {
+            year,
+            month,
+            day,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        })
+    }
+
+    /// `2026-10-01`
+    pub fn date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// `2026-10-01 08:30:00`
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:02}:{:02}:{:02}",
+            self.date(),
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(DateTime::utc(0).to_string(), "1970-01-01 00:00:00");
+        assert_eq!(DateTime::utc(951825599).to_string(), "2000-02-29 11:59:59");
+        assert_eq!(DateTime::utc(1792281600).to_string(), "2026-10-18 00:00:00");
+        assert_eq!(DateTime::utc(4107542400).date(), "2100-03-01");
+
+        let date = DateTime::parse_date("2026-1-05").unwrap();
+        assert_eq!(date.to_string(), "2026-01-05 00:00:00");
+        assert!(DateTime::parse_date("2024-02-29").is_some());
+        assert!(DateTime::parse_date("2100-02-29").is_none());
+        assert!(DateTime::parse_date("2026-13-01").is_none());
+        assert!(DateTime::parse_date("2026-10").is_none());
+        assert!(DateTime::parse_date("yesterday").is_none());
+    }
+}
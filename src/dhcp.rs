@@ -0,0 +1,642 @@
+// Only the packet format is needed off Linux, for `interfaces`
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::error::Error;
+use std::net::Ipv4Addr;
+
+use crate::config::Config;
+
+#[cfg(target_os = "linux")]
+use crate::config;
+#[cfg(target_os = "linux")]
+use crate::netlink;
+#[cfg(target_os = "linux")]
+use crate::{debug, info, warn};
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::net::{SocketAddrV4, UdpSocket};
+#[cfg(target_os = "linux")]
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+#[cfg(target_os = "linux")]
+use std::thread;
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const MAGIC: [u8; 4] = [99, 130, 83, 99];
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETERS: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_CLIENT_ID: u8 = 61;
+const OPT_PAD: u8 = 0;
+const OPT_END: u8 = 255;
+
+/// How long to try obtaining or renewing a lease.
+#[cfg(target_os = "linux")]
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// The routes through the router of an interface go to this table plus the
+/// interface index.
+#[cfg(target_os = "linux")]
+const ROUTE_TABLES: u32 = 1000;
+
+/// An address assigned by a DHCP server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub addr: Ipv4Addr,
+    pub prefix: u8,
+    pub server: Ipv4Addr,
+    /// The first router the server named, if any
+    pub router: Option<Ipv4Addr>,
+    /// Lease time in seconds, `u32::MAX` for infinite
+    pub lease_time: u32,
+    /// Seconds after which the lease is renewed
+    pub renewal_time: u32,
+}
+
+/// The fields of a DHCP message a client needs.
+#[derive(Debug, Clone, PartialEq)]
+struct Packet {
+    op: u8,
+    xid: u32,
+    ciaddr: Ipv4Addr,
+    yiaddr: Ipv4Addr,
+    chaddr: [u8; 6],
+    options: Vec<(u8, Vec<u8>)>,
+}
+
+impl Packet {
+    fn new(op: u8, kind: u8, xid: u32, chaddr: [u8; 6]) -> Self {
+        Self {
+            op,
+            xid,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            options: vec![(OPT_MESSAGE_TYPE, vec![kind])],
+        }
+    }
+
+    /// A client message identifying itself by its MAC address.
+    fn request(kind: u8, xid: u32, mac: [u8; 6]) -> Self {
+        let mut packet = Self::new(BOOTREQUEST, kind, xid, mac);
+        let mut client_id = vec![1];
+        client_id.extend_from_slice(&mac);
+        packet.options.push((OPT_CLIENT_ID, client_id));
+        packet.options.push((
+            OPT_PARAMETERS,
+            vec![
+                OPT_SUBNET_MASK,
+                OPT_ROUTER,
+                OPT_LEASE_TIME,
+                OPT_SERVER_ID,
+                OPT_RENEWAL_TIME,
+            ],
+        ));
+        packet
+    }
+
+    fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    fn option_addr(&self, code: u8) -> Option<Ipv4Addr> {
+        let octets: [u8; 4] = self.option(code)?.try_into().ok()?;
+        Some(Ipv4Addr::from(octets))
+    }
+
+    fn option_u32(&self, code: u8) -> Option<u32> {
+        Some(u32::from_be_bytes(self.option(code)?.try_into().ok()?))
+    }
+
+    fn message_type(&self) -> Option<u8> {
+        self.option(OPT_MESSAGE_TYPE)?.first().copied()
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0; 236];
+        buf[0] = self.op;
+        buf[1] = 1; // Ethernet
+        buf[2] = 6;
+        buf[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        // Ask for broadcast replies, we cannot receive unicast before having
+        // an address
+        buf[10] = 0x80;
+        buf[12..16].copy_from_slice(&self.ciaddr.octets());
+        buf[16..20].copy_from_slice(&self.yiaddr.octets());
+        buf[28..34].copy_from_slice(&self.chaddr);
+        buf.extend_from_slice(&MAGIC);
+        for (code, data) in &self.options {
+            buf.push(*code);
+            buf.push(data.len() as u8);
+            buf.extend_from_slice(data);
+        }
+        buf.push(OPT_END);
+        // Some servers drop messages shorter than a BOOTP message
+        if buf.len() < 300 {
+            buf.resize(300, OPT_PAD);
+        }
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 240 || buf[236..240] != MAGIC {
+            return None;
+        }
+        let addr = |at: usize| Ipv4Addr::new(buf[at], buf[at + 1], buf[at + 2], buf[at + 3]);
+        let mut options = Vec::new();
+        let mut rest = &buf[240..];
+        while let [code, tail @ ..] = rest {
+            match *code {
+                OPT_PAD => rest = tail,
+                OPT_END => break,
+                code => {
+                    let (&len, tail) = tail.split_first()?;
+                    let data = tail.get(..len as usize)?;
+                    options.push((code, data.to_vec()));
+                    rest = &tail[len as usize..];
+                }
+            }
+        }
+        Some(Self {
+            op: buf[0],
+            xid: u32::from_be_bytes(buf[4..8].try_into().unwrap()),
+            ciaddr: addr(12),
+            yiaddr: addr(16),
+            chaddr: buf[28..34].try_into().unwrap(),
+            options,
+        })
+    }
+
+    /// The lease granted by an ACK.
+    fn lease(&self) -> Result<Lease, Box<dyn Error>> {
+        let server = self
+            .option_addr(OPT_SERVER_ID)
+            .ok_or("DHCP reply without server identifier")?;
+        let prefix = self
+            .option_addr(OPT_SUBNET_MASK)
+            .map_or(24, |mask| u32::from(mask).count_ones() as u8);
+        let router = self
+            .option(OPT_ROUTER)
+            .and_then(|routers| <[u8; 4]>::try_from(routers.get(..4)?).ok())
+            .map(Ipv4Addr::from);
+        let lease_time = self.option_u32(OPT_LEASE_TIME).unwrap_or(3600);
+        let renewal_time = self.option_u32(OPT_RENEWAL_TIME).unwrap_or(lease_time / 2);
+        Ok(Lease {
+            addr: self.yiaddr,
+            prefix,
+            server,
+            router,
+            lease_time,
+            renewal_time,
+        })
+    }
+}
+
+/// Interfaces whose address srun obtains itself: those of users with `dhcp`,
+/// and the macvlans of `multidial` with `dhcp`.
+pub fn interfaces(config: &Config) -> Vec<String> {
+    let mut ifaces: Vec<String> = config
+        .users
+        .iter()
+        .filter(|user| user.dhcp)
+        .filter_map(|user| user.iface.clone())
+        .collect();
+    if let Some(multidial) = &config.multidial
+        && multidial.dhcp
+    {
+        ifaces.extend(
+            multidial
+                .plan(&config.users)
+                .into_iter()
+                .map(|(_, name, _)| name),
+        );
+    }
+    ifaces.sort();
+    ifaces.dedup();
+    ifaces
+}
+
+/// One DHCP exchange on an interface.
+#[cfg(target_os = "linux")]
+struct Client {
+    iface: String,
+    mac: [u8; 6],
+    socket: UdpSocket,
+    xid: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl Client {
+    fn new(iface: &str) -> Result<Self, Box<dyn Error>> {
+        let mac = netlink::mac_address(iface)
+            .ok_or_else(|| format!("Network interface {} not found", iface))?;
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos();
+        Ok(Self {
+            iface: iface.to_string(),
+            mac,
+            socket: bind(iface, CLIENT_PORT)?,
+            xid: nanos ^ std::process::id().rotate_left(16),
+        })
+    }
+
+    /// Broadcasts `packet` until a reply of one of the `expected` types
+    /// arrives, backing off between attempts.
+    fn exchange(
+        &self,
+        packet: &Packet,
+        expected: &[u8],
+        deadline: Instant,
+    ) -> Result<Packet, Box<dyn Error>> {
+        let data = packet.encode();
+        let mut wait = Duration::from_secs(2);
+        loop {
+            self.socket
+                .send_to(&data, (Ipv4Addr::BROADCAST, SERVER_PORT))?;
+            let resend = Instant::now() + wait;
+            loop {
+                let timeout = resend
+                    .min(deadline)
+                    .saturating_duration_since(Instant::now());
+                if timeout.is_zero() {
+                    break;
+                }
+                self.socket.set_read_timeout(Some(timeout))?;
+                let mut buf = [0; 1500];
+                let n = match self.socket.recv(&mut buf) {
+                    Ok(n) => n,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if let Some(reply) = Packet::decode(&buf[..n])
+                    && reply.op == BOOTREPLY
+                    && reply.xid == self.xid
+                    && reply.chaddr == self.mac
+                    && reply.message_type().is_some_and(|t| expected.contains(&t))
+                {
+                    return Ok(reply);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(format!("No DHCP reply on {}", self.iface).into());
+            }
+            wait = (wait * 2).min(Duration::from_secs(16));
+        }
+    }
+
+    /// DISCOVER, OFFER, REQUEST, ACK.
+    fn obtain(&self, timeout: Duration) -> Result<Lease, Box<dyn Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let discover = Packet::request(DISCOVER, self.xid, self.mac);
+            let offer = self.exchange(&discover, &[OFFER], deadline)?;
+            let server = offer
+                .option(OPT_SERVER_ID)
+                .ok_or("DHCP offer without server identifier")?;
+            debug!("DHCP offer of {} on {}", offer.yiaddr, self.iface);
+
+            let mut request = Packet::request(REQUEST, self.xid, self.mac);
+            request
+                .options
+                .push((OPT_REQUESTED_IP, offer.yiaddr.octets().to_vec()));
+            request.options.push((OPT_SERVER_ID, server.to_vec()));
+            let reply = self.exchange(&request, &[ACK, NAK], deadline)?;
+            if reply.message_type() == Some(ACK) {
+                return reply.lease();
+            }
+            warn!("DHCP request on {} was refused, starting over", self.iface);
+        }
+    }
+
+    /// REQUEST with the current address, answered by ACK or NAK.
+    fn renew(&self, lease: &Lease, timeout: Duration) -> Result<Lease, Box<dyn Error>> {
+        let mut request = Packet::request(REQUEST, self.xid, self.mac);
+        request.ciaddr = lease.addr;
+        let reply = self.exchange(&request, &[ACK, NAK], Instant::now() + timeout)?;
+        if reply.message_type() == Some(NAK) {
+            return Err(format!("DHCP server refused to renew {}", lease.addr).into());
+        }
+        reply.lease()
+    }
+}
+
+/// A UDP socket on `port` of `iface` that can broadcast before the interface
+/// has an address.
+#[cfg(target_os = "linux")]
+fn bind(iface: &str, port: u16) -> io::Result<UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV4,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket.bind_device(Some(iface.as_bytes()))?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+    Ok(socket.into())
+}
+
+/// Assigns the leased address, replacing the previous one, and routes the
+/// traffic from it through the router. The kernel drops the address when the
+/// lease expires.
+#[cfg(target_os = "linux")]
+fn apply(iface: &str, lease: &Lease, old: Option<&Lease>) -> Result<(), Box<dyn Error>> {
+    let table = ROUTE_TABLES + netlink::index(iface)?;
+    if let Some(old) = old
+        && (old.addr, old.prefix) != (lease.addr, lease.prefix)
+    {
+        let _ = netlink::delete_address(iface, old.addr, old.prefix);
+        if old.router.is_some() {
+            let _ = netlink::delete_source_rule(old.addr, table);
+        }
+    }
+    netlink::add_address(iface, lease.addr, lease.prefix, lease.lease_time)?;
+    if let Some(router) = lease.router {
+        netlink::add_source_route(iface, lease.addr, router, table)?;
+    }
+    info!(
+        "Got {}/{} on {} from {} for {} s",
+        lease.addr, lease.prefix, iface, lease.server, lease.lease_time
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn acquire(iface: &str) -> Result<Lease, Box<dyn Error>> {
+    let lease = Client::new(iface)?.obtain(TIMEOUT)?;
+    apply(iface, &lease, None)?;
+    Ok(lease)
+}
+
+/// Renews the lease on `iface` until `stop` is disconnected, starting over
+/// with a new lease once it expired.
+#[cfg(target_os = "linux")]
+fn maintain(iface: String, mut lease: Lease, stop: Receiver<()>) {
+    // 发送端被丢弃即表示接口已不在配置中
+    let wait = |duration| matches!(stop.recv_timeout(duration), Err(RecvTimeoutError::Timeout));
+    while lease.lease_time != u32::MAX {
+        let obtained = Instant::now();
+        let expiry = obtained + Duration::from_secs(lease.lease_time.into());
+        if !wait(Duration::from_secs(lease.renewal_time.into())) {
+            return;
+        }
+        let renewed = loop {
+            match Client::new(&iface).and_then(|client| client.renew(&lease, TIMEOUT)) {
+                Ok(renewed) => break Some(renewed),
+                Err(e) => {
+                    let left = expiry.saturating_duration_since(Instant::now());
+                    warn!("Renewing the lease on {} failed: {}", iface, e);
+                    if left.is_zero() {
+                        break None;
+                    }
+                    if !wait((left / 2).max(Duration::from_secs(10)).min(left)) {
+                        return;
+                    }
+                }
+            }
+        };
+        let new = match renewed {
+            Some(renewed) => renewed,
+            None => loop {
+                match Client::new(&iface).and_then(|client| client.obtain(TIMEOUT)) {
+                    Ok(new) => break new,
+                    Err(e) => {
+                        warn!("Lease on {} expired, no new one: {}", iface, e);
+                        if !wait(Duration::from_secs(10)) {
+                            return;
+                        }
+                    }
+                }
+            },
+        };
+        if let Err(e) = apply(&iface, &new, Some(&lease)) {
+            warn!("Failed to assign {} to {}: {}", new.addr, iface, e);
+        }
+        lease = new;
+    }
+}
+
+/// Leases renewed in the background by the long-running modes.
+#[derive(Debug, Default)]
+pub struct Leases {
+    /// Dropping the sender stops the renewal of the interface's lease
+    #[cfg(target_os = "linux")]
+    running: HashMap<String, Sender<()>>,
+}
+
+impl Leases {
+    /// Obtains a lease on every DHCP interface not handled yet and keeps
+    /// renewing it, and stops renewing those no longer configured.
+    #[cfg(target_os = "linux")]
+    pub fn ensure(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        let ifaces = interfaces(config);
+        self.running.retain(|iface, _| {
+            let keep = ifaces.contains(iface);
+            if !keep {
+                info!("Stopped renewing the lease on {}", iface);
+            }
+            keep
+        });
+        for iface in ifaces {
+            if self.running.contains_key(&iface) {
+                continue;
+            }
+            let lease = acquire(&iface).map_err(|e| format!("DHCP on {}: {}", iface, e))?;
+            let (sender, stop) = mpsc::channel();
+            self.running.insert(iface.clone(), sender);
+            thread::spawn(move || maintain(iface, lease, stop));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn ensure(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        obtain_missing(config)
+    }
+}
+
+/// Obtains a lease on the DHCP interfaces without an IPv4 address, for the
+/// one-shot commands. Nothing renews it, the address expires with the lease.
+#[cfg(target_os = "linux")]
+pub fn obtain_missing(config: &Config) -> Result<(), Box<dyn Error>> {
+    for iface in interfaces(config) {
+        if config::iface_ipv4(&iface).is_err() {
+            acquire(&iface).map_err(|e| format!("DHCP on {}: {}", iface, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn obtain_missing(config: &Config) -> Result<(), Box<dyn Error>> {
+    match interfaces(config).is_empty() {
+        true => Ok(()),
+        false => Err("dhcp is only supported on Linux".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    fn ack(xid: u32, yiaddr: Ipv4Addr) -> Packet {
+        let mut ack = Packet::new(BOOTREPLY, ACK, xid, MAC);
+        ack.yiaddr = yiaddr;
+        ack.options.push((OPT_SERVER_ID, vec![10, 78, 0, 1]));
+        ack.options.push((OPT_SUBNET_MASK, vec![255, 255, 252, 0]));
+        ack.options
+            .push((OPT_ROUTER, vec![10, 78, 0, 1, 10, 78, 0, 2]));
+        ack.options
+            .push((OPT_LEASE_TIME, 7200u32.to_be_bytes().to_vec()));
+        ack
+    }
+
+    #[test]
+    fn test_packet() {
+        let mut request = Packet::request(REQUEST, 0xdeadbeef, MAC);
+        request.ciaddr = Ipv4Addr::new(10, 78, 0, 10);
+        let buf = request.encode();
+        assert_eq!(buf.len(), 300);
+        assert_eq!(buf[10], 0x80);
+        let decoded = Packet::decode(&buf).unwrap();
+        assert_eq!(decoded, request);
+        assert_eq!(decoded.message_type(), Some(REQUEST));
+        assert_eq!(
+            decoded.option(OPT_CLIENT_ID),
+            Some(&[1, 2, 0x11, 0x22, 0x33, 0x44, 0x55][..])
+        );
+
+        let lease = ack(1, Ipv4Addr::new(10, 78, 0, 10)).lease().unwrap();
+        assert_eq!(
+            lease,
+            Lease {
+                addr: Ipv4Addr::new(10, 78, 0, 10),
+                prefix: 22,
+                server: Ipv4Addr::new(10, 78, 0, 1),
+                router: Some(Ipv4Addr::new(10, 78, 0, 1)),
+                lease_time: 7200,
+                renewal_time: 3600,
+            }
+        );
+
+        let mut anonymous = ack(1, Ipv4Addr::new(10, 78, 0, 10));
+        anonymous.options.retain(|(code, _)| *code != OPT_SERVER_ID);
+        assert!(anonymous.lease().is_err());
+        let mut truncated = ack(1, Ipv4Addr::new(10, 78, 0, 10)).encode();
+        truncated.truncate(245);
+        assert!(Packet::decode(&truncated).is_none());
+        assert!(Packet::decode(&buf[..239]).is_none());
+    }
+
+    /// Answers `replies` requests on `iface` in the network namespace `netns`
+    /// like a DHCP server leasing `addr`.
+    #[cfg(target_os = "linux")]
+    fn serve(
+        netns: &str,
+        iface: &str,
+        addr: Ipv4Addr,
+        replies: usize,
+    ) -> thread::JoinHandle<Vec<u8>> {
+        use std::os::fd::AsRawFd;
+
+        let netns = std::fs::File::open(format!("/run/netns/{}", netns)).unwrap();
+        let iface = iface.to_string();
+        let (ready, started) = std::sync::mpsc::channel();
+        let server = thread::spawn(move || {
+            // Only this thread enters the namespace
+            assert_eq!(
+                unsafe { libc::setns(netns.as_raw_fd(), libc::CLONE_NEWNET) },
+                0
+            );
+            let socket = bind(&iface, SERVER_PORT).unwrap();
+            ready.send(()).unwrap();
+            let mut kinds = Vec::new();
+            let mut buf = [0; 1500];
+            while kinds.len() < replies {
+                let n = socket.recv(&mut buf).unwrap();
+                let request = Packet::decode(&buf[..n]).unwrap();
+                let kind = request.message_type().unwrap();
+                kinds.push(kind);
+                let mut reply = ack(request.xid, addr);
+                reply.chaddr = request.chaddr;
+                reply.options[0].1 = vec![if kind == DISCOVER { OFFER } else { ACK }];
+                socket
+                    .send_to(&reply.encode(), (Ipv4Addr::BROADCAST, CLIENT_PORT))
+                    .unwrap();
+            }
+            kinds
+        });
+        started.recv().unwrap();
+        server
+    }
+
+    /// Needs CAP_NET_ADMIN, e.g. `sudo unshare -n cargo test -- --ignored`
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore]
+    fn test_obtain_and_renew() -> Result<(), Box<dyn Error>> {
+        use std::process::Command;
+
+        let ip = |args: &str| {
+            let status = Command::new("ip").args(args.split(' ')).status().unwrap();
+            assert!(status.success(), "ip {}", args);
+        };
+        // The server needs its own namespace, the kernel drops packets coming
+        // from one of its own addresses
+        ip("netns add srdhcp");
+        ip("link add srdhcp-c type veth peer name srdhcp-s netns srdhcp");
+        ip("-n srdhcp addr add 10.78.0.1/22 dev srdhcp-s");
+        ip("-n srdhcp link set srdhcp-s up");
+        ip("link set srdhcp-c up");
+
+        let addr = Ipv4Addr::new(10, 78, 0, 10);
+        let server = serve("srdhcp", "srdhcp-s", addr, 3);
+        let lease = acquire("srdhcp-c")?;
+        assert_eq!((lease.addr, lease.prefix), (addr, 22));
+        assert_eq!(config::iface_ipv4("srdhcp-c")?, addr);
+        let show = |args: &str| {
+            let output = Command::new("ip").args(args.split(' ')).output().unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+        let table = ROUTE_TABLES + netlink::index("srdhcp-c")?;
+        assert!(
+            show(&format!("-4 route show table {}", table))
+                .starts_with("default via 10.78.0.1 dev srdhcp-c")
+        );
+        assert!(show("-4 rule").contains(&format!("from 10.78.0.10 lookup {}", table)));
+        let renewed = Client::new("srdhcp-c")?.renew(&lease, TIMEOUT)?;
+        assert_eq!(renewed, lease);
+        assert_eq!(server.join().unwrap(), [DISCOVER, REQUEST, REQUEST]);
+        ip("netns del srdhcp");
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ pub enum SrunError {
     Logout(&'static str),
     Config(&'static str),
     Network(&'static str),
+    Decode(&'static str),
 }
 
 impl fmt::Display for SrunError {
@@ -19,6 +20,7 @@ impl fmt::Display for SrunError {
             SrunError::Logout(msg) => write!(f, "Logout error: {}", msg),
             SrunError::Config(msg) => write!(f, "Config error: {}", msg),
             SrunError::Network(msg) => write!(f, "Network error: {}", msg),
+            SrunError::Decode(msg) => write!(f, "Decode error: {}", msg),
         }
     }
 }
@@ -30,7 +32,7 @@ pub enum HttpError {
     Connection(&'static str),
     Protocol(&'static str),
     Tls(&'static str),
-    Parse(&'static str),
+    Parse(String),
 }
 
 impl fmt::Display for HttpError {
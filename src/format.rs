@@ -0,0 +1,164 @@
+use crate::datetime::DateTime;
+use crate::srun::InfoResponse;
+
+/// `512 B`, `1.50 KiB`, `12.34 GiB`
+pub fn bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, UNITS[unit])
+}
+
+/// `45s`, `12m 5s`, `3h 12m`
+pub fn duration(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds / 60 % 60),
+    }
+}
+
+/// Bytes in adaptive units, or the exact number if `raw`.
+pub fn size(bytes: u64, raw: bool) -> String {
+    if raw {
+        bytes.to_string()
+    } else {
+        self::bytes(bytes)
+    }
+}
+
+/// A Unix timestamp in local time, or the timestamp itself if `raw`.
+pub fn timestamp(timestamp: u64, raw: bool) -> String {
+    if raw {
+        timestamp.to_string()
+    } else {
+        DateTime::local(timestamp).to_string()
+    }
+}
+
+/// Seconds as hours and minutes, or the exact number if `raw`.
+pub fn seconds(seconds: u64, raw: bool) -> String {
+    if raw {
+        seconds.to_string()
+    } else {
+        duration(seconds)
+    }
+}
+
+/// The session described by `rad_user_info` of an online user, one line per
+/// topic. Fields the portal did not send are left out.
+pub fn online_info(info: &InfoResponse, raw: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    let mut session = format!("Already logged in at {}", info.online_ip);
+    if let Some(mac) = &info.user_mac {
+        session.push_str(&format!(" ({})", mac));
+    }
+    if let Some(user_name) = &info.user_name {
+        session.push_str(&format!(" as {}", user_name));
+    }
+    session.push('.');
+    lines.push(session);
+
+    let traffic: Vec<String> = [
+        ("Bytes in", info.bytes_in),
+        ("bytes out", info.bytes_out),
+        ("all bytes", info.all_bytes),
+        ("sum bytes", info.sum_bytes),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some(format!("{}: {}", name, size(value?, raw))))
+    .collect();
+    if !traffic.is_empty() {
+        let mut traffic = traffic.join(", ");
+        traffic[..1].make_ascii_uppercase();
+        lines.push(traffic);
+    }
+    if let Some(sum_seconds) = info.sum_seconds {
+        lines.push(format!("Online time: {}", seconds(sum_seconds, raw)));
+    }
+    if let Some(add_time) = info.add_time {
+        lines.push(format!("Online since: {}", timestamp(add_time, raw)));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        assert_eq!(bytes(0), "0 B");
+        assert_eq!(bytes(1023), "1023 B");
+        assert_eq!(bytes(1536), "1.50 KiB");
+        assert_eq!(bytes(5 * 1024 * 1024 * 1024), "5.00 GiB");
+        assert_eq!(bytes(u64::MAX), "16384.00 PiB");
+        assert_eq!(duration(45), "45s");
+        assert_eq!(duration(725), "12m 5s");
+        assert_eq!(duration(11520), "3h 12m");
+        assert_eq!(duration(90000), "25h 0m");
+        assert_eq!(size(1536, false), "1.50 KiB");
+        assert_eq!(size(1536, true), "1536");
+        assert_eq!(seconds(725, true), "725");
+        assert_eq!(timestamp(1792281600, true), "1792281600");
+    }
+
+    #[test]
+    fn test_online_info() {
+        let mut info = InfoResponse {
+            online_ip: String::from("10.0.0.2"),
+            user_mac: Some(String::from("02:00:00:00:00:01")),
+            user_name: Some(String::from("alice")),
+            bytes_in: Some(1610612736),
+            bytes_out: Some(209715200),
+            all_bytes: Some(1820327936),
+            sum_bytes: Some(53687091200),
+            sum_seconds: Some(11520),
+            add_time: Some(1792281600),
+            ..Default::default()
+        };
+        let lines = online_info(&info, false);
+        assert_eq!(
+            lines[..3],
+            [
+                "Already logged in at 10.0.0.2 (02:00:00:00:00:01) as alice.",
+                "Bytes in: 1.50 GiB, bytes out: 200.00 MiB, all bytes: 1.70 GiB, sum bytes: 50.00 GiB",
+                "Online time: 3h 12m",
+            ]
+        );
+        assert!(lines[3].starts_with("Online since: 2026-10-"));
+        assert_eq!(
+            online_info(&info, true)[1..],
+            [
+                "Bytes in: 1610612736, bytes out: 209715200, all bytes: 1820327936, sum bytes: 53687091200",
+                "Online time: 11520",
+                "Online since: 1792281600",
+            ]
+        );
+
+        // Fields the portal did not send
+        info.user_mac = None;
+        info.bytes_in = None;
+        info.bytes_out = None;
+        info.all_bytes = None;
+        info.sum_seconds = None;
+        info.add_time = None;
+        assert_eq!(
+            online_info(&info, false),
+            [
+                "Already logged in at 10.0.0.2 as alice.",
+                "Sum bytes: 50.00 GiB",
+            ]
+        );
+        info.sum_bytes = None;
+        assert_eq!(online_info(&info, false).len(), 1);
+    }
+}
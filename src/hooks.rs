@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::config::User;
+use crate::srun::SrunClient;
+use crate::{debug, warn};
+
+/// Shell commands run on events, with the event described in `SRUN_*`
+/// environment variables.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hooks {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_login: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_logout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_offline_detected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_login_failed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_ip_changed: Option<String>,
+    /// Hooks still running after this many milliseconds are killed
+    pub timeout: u64,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            on_login: None,
+            on_logout: None,
+            on_offline_detected: None,
+            on_login_failed: None,
+            on_ip_changed: None,
+            timeout: 30000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Login,
+    Logout,
+    OfflineDetected,
+    LoginFailed,
+    IpChanged,
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Login => "login",
+            Event::Logout => "logout",
+            Event::OfflineDetected => "offline_detected",
+            Event::LoginFailed => "login_failed",
+            Event::IpChanged => "ip_changed",
+        }
+    }
+}
+
+/// What is known about an event, passed to the hook as environment.
+#[derive(Debug, Clone, Default)]
+pub struct EventInfo {
+    pub user: String,
+    pub ip: Option<String>,
+    pub old_ip: Option<String>,
+    pub mac: Option<String>,
+    /// `error` of the server response, or the local error
+    pub error: Option<String>,
+    /// `ecode` of the server response, if it sent one
+    pub ecode: Option<String>,
+    pub error_msg: Option<String>,
+    pub suc_msg: Option<String>,
+}
+
+impl EventInfo {
+    pub fn new(user: &str, ip: Option<String>) -> Self {
+        Self {
+            user: user.to_string(),
+            ip,
+            ..Default::default()
+        }
+    }
+
+    /// Describes a login or logout, preferring the portal's response.
+    pub fn from_client(
+        user: &User,
+        client: &SrunClient,
+        result: &Result<(), Box<dyn Error>>,
+    ) -> Self {
+        let mut event = Self::new(&user.username, user.bind_addr.map(|ip| ip.to_string()));
+        if let Some(resp) = client.last_response() {
+            if !resp.online_ip.is_empty() {
+                event.ip = Some(resp.online_ip.clone());
+            }
+            event.error = Some(resp.error.clone());
+            event.ecode = resp.ecode.is_set().then(|| resp.ecode.to_string());
+            event.error_msg = Some(resp.error_msg.clone());
+            event.suc_msg = Some(resp.suc_msg.clone());
+        }
+        if let Err(e) = result
+            && event.error.as_deref().is_none_or(|error| error == "ok")
+        {
+            event.error = Some(e.to_string());
+        }
+        event
+    }
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn command(&self, event: Event) -> Option<&str> {
+        match event {
+            Event::Login => &self.on_login,
+            Event::Logout => &self.on_logout,
+            Event::OfflineDetected => &self.on_offline_detected,
+            Event::LoginFailed => &self.on_login_failed,
+            Event::IpChanged => &self.on_ip_changed,
+        }
+        .as_deref()
+    }
+
+    /// Starts the hook of `event`, if any, without waiting for it. Join the
+    /// returned handle to wait until the hook has exited or was killed.
+    pub fn fire(&self, event: Event, info: &EventInfo) -> Option<JoinHandle<()>> {
+        let command = self.command(event)?;
+        debug!("Running {} hook for {}", event.name(), info.user);
+        let env = [
+            ("SRUN_EVENT", Some(event.name())),
+            ("SRUN_USER", Some(info.user.as_str())),
+            ("SRUN_IP", info.ip.as_deref()),
+            ("SRUN_OLD_IP", info.old_ip.as_deref()),
+            ("SRUN_MAC", info.mac.as_deref()),
+            ("SRUN_ERROR", info.error.as_deref()),
+            ("SRUN_ECODE", info.ecode.as_deref()),
+            ("SRUN_ERROR_MSG", info.error_msg.as_deref()),
+            ("SRUN_SUC_MSG", info.suc_msg.as_deref()),
+        ];
+        let env = env
+            .into_iter()
+            .map(|(key, value)| (key, value.unwrap_or_default().to_string()));
+        spawn(command, env, Duration::from_millis(self.timeout))
+    }
+}
+
+/// Runs `command` through the shell on a background thread, killing it and
+/// the processes it started after `timeout`.
+pub fn spawn(
+    command: &str,
+    env: impl IntoIterator<Item = (&'static str, String)>,
+    timeout: Duration,
+) -> Option<JoinHandle<()>> {
+    let result = shell(command).envs(env).stdin(Stdio::null()).spawn();
+    match result {
+        Ok(child) => {
+            let command = command.to_string();
+            Some(thread::spawn(move || wait(child, &command, timeout)))
+        }
+        Err(e) => {
+            warn!("Failed to run hook {:?}: {}", command, e);
+            None
+        }
+    }
+}
+
+fn wait(mut child: Child, command: &str, timeout: Duration) {
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                if !status.success() {
+                    warn!("Hook {:?} exited with {}", command, status);
+                }
+                return;
+            }
+            Ok(None) if start.elapsed() >= timeout => {
+                warn!("Hook {:?} timed out, killing it", command);
+                kill(&mut child);
+                let _ = child.wait();
+                return;
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => {
+                warn!("Failed to wait for hook {:?}: {}", command, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Kills the process group the hook leads, so that the commands a shell
+/// hook started in the background do not outlive it.
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    // SAFETY: kill only sends a signal; the group exists until the child is reaped
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } != 0 {
+        let _ = child.kill();
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+}
+
+fn shell(command: &str) -> Command {
+    #[cfg(windows)]
+    {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    }
+    #[cfg(not(windows))]
+    {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        cmd
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_fire() {
+        let out = std::env::temp_dir().join(format!("srun-hook-{}", std::process::id()));
+        let hooks = Hooks {
+            on_ip_changed: Some(format!(
+                "echo \"$SRUN_EVENT $SRUN_USER $SRUN_OLD_IP->$SRUN_IP [$SRUN_MAC]\" > {}",
+                out.display()
+            )),
+            ..Default::default()
+        };
+        let info = EventInfo {
+            old_ip: Some(String::from("10.0.0.2")),
+            ..EventInfo::new("alice", Some(String::from("10.0.0.3")))
+        };
+        assert!(hooks.fire(Event::Login, &info).is_none());
+        hooks.fire(Event::IpChanged, &info).unwrap().join().unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "ip_changed alice 10.0.0.2->10.0.0.3 []\n"
+        );
+        let _ = fs::remove_file(&out);
+
+        let mut failed = EventInfo::new("alice", None);
+        failed.ecode = Some(String::from("E2531"));
+        failed.error_msg = Some(String::from("E2531: User not found."));
+        let hooks = Hooks {
+            on_login_failed: Some(format!(
+                "echo \"$SRUN_ECODE|$SRUN_ERROR_MSG\" > {}",
+                out.display()
+            )),
+            ..Default::default()
+        };
+        hooks
+            .fire(Event::LoginFailed, &failed)
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "E2531|E2531: User not found.\n"
+        );
+        let _ = fs::remove_file(&out);
+
+        // A timeout also kills what the hook started in the background
+        let hooks = Hooks {
+            on_login: Some(format!(
+                "(sleep 0.5; echo late > {}) & sleep 5",
+                out.display()
+            )),
+            timeout: 100,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        hooks.fire(Event::Login, &info).unwrap().join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        thread::sleep(Duration::from_secs(1));
+        assert!(!out.exists());
+    }
+}
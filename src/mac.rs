@@ -0,0 +1,147 @@
+use sha1::{Digest, Sha1};
+use std::error::Error;
+
+use crate::config::{Config, User};
+
+#[cfg(target_os = "linux")]
+use crate::error::ConfigError;
+#[cfg(target_os = "linux")]
+use crate::info;
+#[cfg(target_os = "linux")]
+use crate::netlink;
+
+/// A stable, locally administered unicast MAC address for `username`, so
+/// that the portal and the DHCP server see the same device across restarts.
+pub fn derive(username: &str) -> [u8; 6] {
+    let digest = Sha1::digest(username.as_bytes());
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&digest[..6]);
+    mac[0] = (mac[0] & 0xfc) | 0x02;
+    mac
+}
+
+pub fn format(mac: &[u8; 6]) -> String {
+    let octets: Vec<String> = mac.iter().map(|b| format!("{:02x}", b)).collect();
+    octets.join(":")
+}
+
+/// Parses `aa:bb:cc:dd:ee:ff`, also accepting the `-` separators and upper
+/// case some portals report.
+pub fn parse(mac: &str) -> Option<[u8; 6]> {
+    let octets: Vec<u8> = mac
+        .trim()
+        .split([':', '-'])
+        .map(|octet| match octet.len() {
+            2 => u8::from_str_radix(octet, 16).ok(),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    octets.try_into().ok()
+}
+
+/// Sets the configured `mac` on the interfaces of the users, before they get
+/// an address and log in. Interfaces that already have it are left alone.
+#[cfg(target_os = "linux")]
+pub fn apply(config: &Config) -> Result<(), Box<dyn Error>> {
+    for user in &config.users {
+        let (Some(mac), Some(iface)) = (&user.mac, &user.iface) else {
+            continue;
+        };
+        let mac = parse(mac).ok_or(ConfigError::Validation("Invalid MAC address"))?;
+        if netlink::mac_address(iface) == Some(mac) {
+            continue;
+        }
+        netlink::set_mac_address(iface, mac)
+            .map_err(|e| format!("Failed to set the MAC address of {}: {}", iface, e))?;
+        info!(
+            "Set MAC address {} on {} for user {}",
+            format(&mac),
+            iface,
+            user.username
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(config: &Config) -> Result<(), Box<dyn Error>> {
+    match config.users.iter().any(|user| user.mac.is_some()) {
+        true => Err("mac is only supported on Linux".into()),
+        false => Ok(()),
+    }
+}
+
+/// The MAC address of the interface `user` logs in from.
+#[cfg(target_os = "linux")]
+pub fn local(user: &User) -> Option<[u8; 6]> {
+    let iface = match &user.iface {
+        Some(iface) => iface.clone(),
+        None => {
+            let bind_addr = user.bind_addr?;
+            if_addrs::get_if_addrs()
+                .ok()?
+                .into_iter()
+                .find(|iface| iface.ip() == bind_addr)?
+                .name
+        }
+    };
+    netlink::mac_address(&iface)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn local(_user: &User) -> Option<[u8; 6]> {
+    None
+}
+
+/// The MAC address reported by the portal and the local one, if they differ.
+/// Portals that do not see the MAC report nothing or all zeros.
+pub fn mismatch(user: &User, reported: Option<&str>) -> Option<([u8; 6], [u8; 6])> {
+    let reported = parse(reported?).filter(|mac| *mac != [0; 6])?;
+    let local = local(user)?;
+    (reported != local).then_some((reported, local))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_derive() {
+        let mac = [0x02, 0, 0xab, 1, 2, 3];
+        assert_eq!(format(&mac), "02:00:ab:01:02:03");
+        assert_eq!(parse("02:00:ab:01:02:03"), Some(mac));
+        assert_eq!(parse(" 02-00-AB-01-02-03\n"), Some(mac));
+        assert_eq!(parse("02:00:ab:01:02"), None);
+        assert_eq!(parse("02:00:ab:01:02:03:04"), None);
+        assert_eq!(parse("2:00:ab:01:02:03"), None);
+        assert_eq!(parse(""), None);
+
+        let alice = derive("alice");
+        assert_eq!(alice, derive("alice"));
+        assert_ne!(alice, derive("carol"));
+        assert_eq!(alice[0] & 0x03, 0x02);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mismatch() {
+        let user = User {
+            username: String::from("alice"),
+            password: String::from("secret"),
+            ip: None,
+            iface: Some(String::from("lo")),
+            mac: None,
+            dhcp: false,
+            thresholds: None,
+            bind_addr: None,
+        };
+        assert_eq!(local(&user), Some([0; 6]));
+        assert_eq!(
+            mismatch(&user, Some("02-00-00-00-00-01")),
+            Some(([2, 0, 0, 0, 0, 1], [0; 6]))
+        );
+        assert_eq!(mismatch(&user, Some("00:00:00:00:00:00")), None);
+        assert_eq!(mismatch(&user, Some("")), None);
+        assert_eq!(mismatch(&user, None), None);
+    }
+}
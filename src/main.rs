@@ -1,25 +1,74 @@
 use clap::Parser;
 use if_addrs::IfAddr;
 use std::error::Error;
+use std::mem;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 use crate::cli::{Cli, Commands};
-use crate::config::Config;
-use crate::srun::SrunClient;
+use crate::config::{Config, User};
+use crate::control::Request;
+use crate::daemon::Daemon;
+use crate::datetime::DateTime;
+use crate::dhcp::Leases;
+use crate::error::SrunError;
+use crate::hooks::{Event, EventInfo};
+use crate::metrics::MetricsServer;
+use crate::mock::{MockOptions, MockServer};
+use crate::multidial::Macvlans;
+use crate::notify::Dispatcher;
+use crate::srun::{InfoResponse, SrunClient};
+use crate::state::State;
+use crate::xencode::Encoding;
 
+mod alert;
 mod cli;
 mod config;
+mod control;
+mod daemon;
+mod datetime;
+mod dhcp;
 mod error;
+mod format;
+mod hooks;
 mod http;
 mod log;
+mod mac;
+mod metrics;
+mod mock;
+mod multidial;
+#[cfg(target_os = "linux")]
+mod netlink;
+mod notify;
+mod probe;
 mod srun;
+mod state;
+mod usage;
 mod xencode;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
-    let cmd = &cli.command.clone();
+    let config = Config::from_cli(cli.clone());
+    let mut hooks = Vec::new();
+    let result = run(&cli, config, &mut hooks);
+    wait_hooks(hooks);
+    result
+}
+
+/// Runs the command, collecting the hooks it started so they can finish
+/// before exit.
+fn run(
+    cli: &Cli,
+    mut config: Config,
+    hooks: &mut Vec<JoinHandle<()>>,
+) -> Result<(), Box<dyn Error>> {
     let force = cli.force;
-    let mut config = Config::from_cli(cli);
-    match cmd {
+    let target_ip = cli.ip.clone();
+    let events = Dispatcher::new(&config);
+    match &cli.command {
         Commands::GenConfig { file } => {
             info!("Generating default configuration file to {:?}", file);
             Config::generate_example_config(file.clone())?;
@@ -39,25 +88,252 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
             }
         }
-        Commands::Login => {
+        Commands::Decode { challenge, info } => {
+            let encoding = Encoding::new(
+                &config.enc,
+                config.enc_prefix.as_deref(),
+                config.enc_alphabet.as_deref(),
+            )?;
+            println!("{}", xencode::decode_param_i(info, &encoding, challenge)?);
+        }
+        Commands::MockServer {
+            listen,
+            arrears,
+            chunked,
+            delay,
+            max_online,
+        } => {
+            let options = MockOptions {
+                accounts: config
+                    .users
+                    .iter()
+                    .map(|user| (user.username.clone(), user.password.clone()))
+                    .collect(),
+                arrears: arrears.iter().cloned().collect(),
+                chunked: *chunked,
+                delay: Duration::from_millis(*delay),
+                max_online: *max_online,
+            };
+            let server = MockServer::bind(listen, &config, options)?;
+            info!("Mock Srun portal listening on {}", server.local_addr()?);
+            server.run()?;
+        }
+        Commands::Login {
+            dry_run,
+            challenge,
+            show_secrets,
+        } => {
+            if !*dry_run {
+                mac::apply(&config)?;
+                dhcp::obtain_missing(&config)?;
+            }
             check_config(&mut config)?;
             for user in &config.users {
+                if *dry_run {
+                    info!("Dry run for user: {}", user.username);
+                    SrunClient::dry_run(&config, user, challenge.as_deref(), *show_secrets)?;
+                    continue;
+                }
                 info!("Logging in user: {}", user.username);
                 let mut client = SrunClient::new(&config, user.clone())?;
-                let status = check_status(&mut client)?;
+                let (status, online_info) = check_online(&config, user, &mut client, cli.raw)?;
                 if !status || force {
-                    client.login()?;
+                    let result = client.login();
+                    let mut event = EventInfo::from_client(user, &client, &result);
+                    event.mac = online_info.and_then(|online_info| online_info.user_mac);
+                    let kind = if result.is_ok() {
+                        Event::Login
+                    } else {
+                        Event::LoginFailed
+                    };
+                    hooks.extend(events.fire(kind, &event));
+                    result?;
+                    if let Some(probe) = &config.probe {
+                        thread::sleep(Duration::from_millis(config.retry_delay));
+                        if !probe.check(user.bind_addr, &config.cert_verification) {
+                            warn!("Logged in, but the connectivity probe still fails");
+                        }
+                    }
                 }
             }
         }
-        Commands::Logout => {
+        Commands::Status => {
+            check_config(&mut config)?;
+            let mut crossed = false;
+            for user in &config.users {
+                info!("Status of user: {}", user.username);
+                let mut client = SrunClient::new(&config, user.clone())?;
+                let (status, online_info) = check_online(&config, user, &mut client, cli.raw)?;
+                info!("Online: {}", if status { "yes" } else { "no" });
+                if let Some(online_info) = online_info {
+                    if status {
+                        events.sample(&user.username, &online_info);
+                    }
+                    if let Some((reported, local)) =
+                        mac::mismatch(user, online_info.user_mac.as_deref())
+                    {
+                        warn!(
+                            "The portal sees MAC {} but the interface has {}, is there a NAT router in between?",
+                            mac::format(&reported),
+                            mac::format(&local)
+                        );
+                    }
+                    let thresholds = config.thresholds_for(user);
+                    let alerts = thresholds.check(&online_info);
+                    let timeout = Duration::from_millis(config.hooks.timeout);
+                    hooks.extend(thresholds.report(&user.username, &alerts, timeout));
+                    crossed |= !alerts.is_empty();
+                }
+            }
+            if crossed {
+                wait_hooks(mem::take(hooks));
+                process::exit(alert::EXIT_THRESHOLD);
+            }
+        }
+        Commands::Logout { all } => {
+            let remote_ip = take_target_ip(&mut config, target_ip);
+            if *all && remote_ip.is_some() {
+                return Err(SrunError::Config("--all cannot be used with --ip").into());
+            }
             check_config(&mut config)?;
             for user in &config.users {
                 info!("Logging out user: {}", user.username);
                 let mut client = SrunClient::new(&config, user.clone())?;
-                let status = check_status(&mut client)?;
-                if status || force {
-                    client.logout()?;
+                if *all {
+                    let closed = client.kick("")?;
+                    if closed.is_empty() {
+                        info!("Closed all sessions of {}", user.username);
+                    }
+                    for ip in closed {
+                        info!("Closed session at {}", ip);
+                        let event = EventInfo::new(&user.username, Some(ip));
+                        hooks.extend(events.fire(Event::Logout, &event));
+                    }
+                } else if let Some(ip) = &remote_ip {
+                    // 远程会话的状态无法从本机查询
+                    client.logout_ip(ip)?;
+                    info!("Closed session at {}", ip);
+                    let mut event = EventInfo::from_client(user, &client, &Ok(()));
+                    event.ip = Some(ip.clone());
+                    hooks.extend(events.fire(Event::Logout, &event));
+                } else {
+                    let (status, online_info) = check_status(&mut client, cli.raw)?;
+                    if status || force {
+                        client.logout()?;
+                        let mut event = EventInfo::from_client(user, &client, &Ok(()));
+                        event.mac = online_info.user_mac;
+                        hooks.extend(events.fire(Event::Logout, &event));
+                    }
+                }
+            }
+        }
+        Commands::Daemon {
+            interval,
+            metrics,
+            netlink,
+        } => {
+            let mut interfaces = Default::default();
+            prepare_interfaces(&config, &mut interfaces)?;
+            check_config(&mut config)?;
+            let daemon = Daemon::new(config, Duration::from_secs(*interval), true);
+            if let Some(listen) = metrics {
+                let server = MetricsServer::bind(listen, daemon.metrics())?;
+                info!("Serving metrics on http://{}/metrics", server.local_addr()?);
+                server.spawn();
+            }
+            run_daemon(cli, daemon, *netlink, interfaces)?;
+        }
+        Commands::Exporter {
+            listen,
+            interval,
+            keep_online,
+            netlink,
+        } => {
+            let mut interfaces = Default::default();
+            prepare_interfaces(&config, &mut interfaces)?;
+            check_config(&mut config)?;
+            let daemon = Daemon::new(config, Duration::from_secs(*interval), *keep_online);
+            let server = MetricsServer::bind(listen, daemon.metrics())?;
+            info!("Serving metrics on http://{}/metrics", server.local_addr()?);
+            server.spawn();
+            run_daemon(cli, daemon, *netlink, interfaces)?;
+        }
+        Commands::Ctl { action, user } => {
+            let path = config
+                .control_socket
+                .as_deref()
+                .ok_or(SrunError::Config("control_socket is not configured"))?;
+            let request = Request {
+                action: *action,
+                user: user.clone(),
+            };
+            let response = control::send(path, &request)?;
+            if !response.ok {
+                return Err(response.message.into());
+            }
+            if !response.message.is_empty() {
+                println!("{}", response.message);
+            }
+            if !response.users.is_empty() {
+                println!(
+                    "{:<24} {:<7} {:<7} {:<16} LAST REFRESH",
+                    "USER", "ONLINE", "PAUSED", "IP"
+                );
+            }
+            for status in &response.users {
+                println!(
+                    "{:<24} {:<7} {:<7} {:<16} {}",
+                    status.user,
+                    if status.online { "yes" } else { "no" },
+                    if status.paused { "yes" } else { "no" },
+                    status.ip.as_deref().unwrap_or("-"),
+                    format::timestamp(status.last_refresh, cli.raw)
+                );
+            }
+        }
+        Commands::History { user, limit, days } => {
+            let path = config
+                .state
+                .path
+                .as_deref()
+                .ok_or(SrunError::Config("state.path is not configured"))?;
+            let state = State::load(Path::new(path))?;
+            print_history(&state, user.as_deref(), *limit, *days);
+        }
+        Commands::Usage {
+            user,
+            since,
+            period,
+            format,
+        } => {
+            let path = config
+                .state
+                .path
+                .as_deref()
+                .ok_or(SrunError::Config("state.path is not configured"))?;
+            let since = match since {
+                Some(since) => Some(
+                    DateTime::parse_date(since)
+                        .ok_or(SrunError::Config("--since must be a date like 2026-10-01"))?
+                        .date(),
+                ),
+                None => None,
+            };
+            let state = State::load(Path::new(path))?;
+            let rows = usage::report(&state, user.as_deref(), since.as_deref(), *period);
+            print!("{}", usage::render(&rows, *format));
+        }
+        Commands::Kick => {
+            let ip = take_target_ip(&mut config, target_ip)
+                .ok_or(SrunError::Config("kick requires --ip"))?;
+            check_config(&mut config)?;
+            for user in &config.users {
+                info!("Dropping session of user {} at {}", user.username, ip);
+                let mut client = SrunClient::new(&config, user.clone())?;
+                for closed in client.kick(&ip)? {
+                    info!("Closed session at {}", closed);
+                    let event = EventInfo::new(&user.username, Some(closed));
+                    hooks.extend(events.fire(Event::Logout, &event));
                 }
             }
         }
@@ -65,36 +341,196 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Prints per-user event counts over the last `days` days and the `limit`
+/// most recent events.
+fn print_history(state: &State, user: Option<&str>, limit: usize, days: u64) {
+    let users: Vec<_> = state
+        .users
+        .iter()
+        .filter(|(name, _)| user.is_none_or(|user| user == name.as_str()))
+        .collect();
+    if users.is_empty() {
+        info!("No history recorded yet");
+        return;
+    }
+    let since = state::now().saturating_sub(days * 86400);
+    println!("Last {} days:", days);
+    println!(
+        "{:<24} {:<16} {:<20} {:>6} {:>6} {:>7} {:>10}",
+        "USER", "LAST IP", "LAST LOGIN", "LOGINS", "FAILED", "OFFLINE", "IP CHANGES"
+    );
+    for (name, user) in &users {
+        let summary = user.summary(since);
+        let last_login = user
+            .last_login
+            .map(|time| DateTime::local(time).to_string());
+        println!(
+            "{:<24} {:<16} {:<20} {:>6} {:>6} {:>7} {:>10}",
+            name,
+            user.last_ip.as_deref().unwrap_or("-"),
+            last_login.as_deref().unwrap_or("-"),
+            summary.logins,
+            summary.login_failures,
+            summary.offline,
+            summary.ip_changes
+        );
+    }
+
+    let mut records: Vec<_> = users
+        .iter()
+        .flat_map(|(name, user)| user.events.iter().map(move |record| (name, record)))
+        .collect();
+    records.sort_by_key(|(_, record)| record.time);
+    println!();
+    println!(
+        "{:<20} {:<24} {:<17} {:<16} ERROR",
+        "TIME", "USER", "EVENT", "IP"
+    );
+    for (name, record) in records.iter().skip(records.len().saturating_sub(limit)) {
+        let error = match (&record.error, &record.ecode) {
+            (Some(error), Some(ecode)) => format!("{} ({})", error, ecode),
+            (Some(error), None) => error.clone(),
+            (None, Some(ecode)) => ecode.clone(),
+            (None, None) => String::new(),
+        };
+        println!(
+            "{:<20} {:<24} {:<17} {:<16} {}",
+            DateTime::local(record.time).to_string(),
+            name,
+            record.event.name(),
+            record.ip.as_deref().unwrap_or("-"),
+            error
+        );
+    }
+}
+
+/// Runs the daemon with the control socket, if configured, and reloading
+/// from the same command line. The macvlans are removed when it stops.
+fn run_daemon(
+    cli: &Cli,
+    mut daemon: Daemon,
+    netlink: bool,
+    interfaces: (Macvlans, Leases),
+) -> Result<(), Box<dyn Error>> {
+    let reload_cli = cli.clone();
+    let interfaces = Arc::new(Mutex::new(interfaces));
+    let reload_interfaces = interfaces.clone();
+    daemon.set_loader(Box::new(move || {
+        let mut config = Config::try_from_cli(reload_cli.clone())?;
+        prepare_interfaces(&config, &mut reload_interfaces.lock().unwrap())?;
+        config.check()?;
+        Ok(config)
+    }));
+    #[cfg(unix)]
+    daemon::listen_signals();
+    if daemon.config().reload.watch
+        && let Some(path) = &cli.config
+    {
+        daemon.watch(path);
+    }
+    if let Some(path) = &daemon.config().control_socket {
+        #[cfg(unix)]
+        {
+            let server = control::ControlServer::bind(path)?;
+            info!("Control socket listening on {}", path);
+            server.spawn(daemon.control());
+        }
+        #[cfg(not(unix))]
+        warn!("Control socket {} is only supported on Unix", path);
+    }
+    if netlink {
+        #[cfg(target_os = "linux")]
+        daemon.set_address_changes(netlink::address_changes()?);
+        #[cfg(not(target_os = "linux"))]
+        warn!("--netlink is only supported on Linux");
+    }
+    daemon.run()
+}
+
+fn wait_hooks(hooks: Vec<JoinHandle<()>>) {
+    for hook in hooks {
+        let _ = hook.join();
+    }
+}
+
+/// For commands acting on a remote session, `--ip` names that session rather
+/// than a local address, so it is removed from the users it was applied to.
+fn take_target_ip(config: &mut Config, ip: Option<String>) -> Option<String> {
+    let ip = ip?;
+    for user in &mut config.users {
+        if user.ip.as_ref() == Some(&ip) {
+            user.ip = None;
+        }
+    }
+    Some(ip)
+}
+
+/// Creates the macvlans, sets the MAC addresses and starts the DHCP clients
+/// of the long-running modes.
+fn prepare_interfaces(
+    config: &Config,
+    (macvlans, leases): &mut (Macvlans, Leases),
+) -> Result<(), Box<dyn Error>> {
+    macvlans.ensure(config)?;
+    mac::apply(config)?;
+    leases.ensure(config)
+}
+
 fn check_config(config: &mut Config) -> Result<(), Box<dyn Error>> {
     config.check()?;
     println!("{:#?}", config);
     Ok(())
 }
 
-fn check_status(client: &mut SrunClient) -> Result<bool, Box<dyn Error>> {
+/// Combines the portal status with the connectivity probe, if configured.
+/// The portal's answer is returned unless the status query failed.
+fn check_online(
+    config: &Config,
+    user: &User,
+    client: &mut SrunClient,
+    raw: bool,
+) -> Result<(bool, Option<InfoResponse>), Box<dyn Error>> {
+    let status = check_status(client, raw);
+    let Some(probe) = &config.probe else {
+        let (status, online_info) = status?;
+        return Ok((status, Some(online_info)));
+    };
+    let reachable = probe.check(user.bind_addr, &config.cert_verification);
+    info!(
+        "Connectivity probe: {}",
+        if reachable { "passed" } else { "failed" }
+    );
+    match status {
+        Ok((true, online_info)) if !reachable => {
+            warn!("Portal reports online, but traffic does not pass");
+            Ok((false, Some(online_info)))
+        }
+        Ok((status, online_info)) => Ok((status, Some(online_info))),
+        Err(e) if reachable => {
+            warn!("Status query failed ({}), but traffic passes", e);
+            Ok((true, None))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn check_status(
+    client: &mut SrunClient,
+    raw: bool,
+) -> Result<(bool, InfoResponse), Box<dyn Error>> {
     let (status, online_info) = client.check_status()?;
     if status {
-        info!(
-            "Already logged in at {} ({}) as {}.",
-            online_info.online_ip,
-            online_info.user_mac.unwrap(),
-            online_info.user_name.unwrap()
-        );
-        info!(
-            "Bytes in: {} M, bytes out: {} M. All bytes: {} M. Sum bytes: {} M, Sum Hours: {} H",
-            online_info.bytes_in.unwrap() / 1048576,
-            online_info.bytes_out.unwrap() / 1048576,
-            (online_info.all_bytes.unwrap()) / 1048576,
-            online_info.sum_bytes.unwrap() / 1048576,
-            online_info.sum_seconds.unwrap() / 3600
-        );
-        info!("Online since: {}", online_info.add_time.unwrap());
-        debug!("Srun Version: {}", online_info.sysver.unwrap());
+        for line in format::online_info(&online_info, raw) {
+            info!("{}", line);
+        }
+        if let Some(sysver) = &online_info.sysver {
+            debug!("Srun Version: {}", sysver);
+        }
     } else {
         info!(
             "Not logged in. Current online IP: {}",
             online_info.online_ip
         );
     }
-    Ok(status)
+    Ok((status, online_info))
 }
@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::debug;
+use crate::srun::InfoResponse;
+
+/// Latest `rad_user_info` values and login counters of one user.
+#[derive(Debug, Clone, Default)]
+pub struct UserMetrics {
+    /// Whether the last status query succeeded
+    pub up: bool,
+    pub online: bool,
+    pub bytes_in: Option<u64>,
+    pub bytes_out: Option<u64>,
+    pub sum_bytes: Option<u64>,
+    pub sum_seconds: Option<u64>,
+    pub remain_seconds: Option<u64>,
+    pub user_balance: Option<f64>,
+    pub wallet_balance: Option<f64>,
+    pub login_attempts: u64,
+    pub login_failures: u64,
+    pub login_latency: Option<Duration>,
+    /// Unix time of the last refresh
+    pub last_refresh: u64,
+}
+
+impl UserMetrics {
+    pub fn update(&mut self, online: bool, info: &InfoResponse) {
+        self.up = true;
+        self.online = online;
+        self.bytes_in = info.bytes_in;
+        self.bytes_out = info.bytes_out;
+        self.sum_bytes = info.sum_bytes;
+        self.sum_seconds = info.sum_seconds;
+        self.remain_seconds = info.remain_seconds;
+        self.user_balance = info.user_balance;
+        self.wallet_balance = info.wallet_balance;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub users: BTreeMap<String, UserMetrics>,
+}
+
+type Sample = fn(&UserMetrics) -> Option<f64>;
+
+const METRICS: &[(&str, &str, &str, Sample)] = &[
+    (
+        "srun_up",
+        "gauge",
+        "Whether the last status query succeeded",
+        |m| Some(m.up as u8 as f64),
+    ),
+    (
+        "srun_online",
+        "gauge",
+        "Whether the user is online from its address",
+        |m| Some(m.online as u8 as f64),
+    ),
+    (
+        "srun_session_bytes_in",
+        "gauge",
+        "Bytes received in the current session",
+        |m| m.bytes_in.map(|v| v as f64),
+    ),
+    (
+        "srun_session_bytes_out",
+        "gauge",
+        "Bytes sent in the current session",
+        |m| m.bytes_out.map(|v| v as f64),
+    ),
+    (
+        "srun_sum_bytes",
+        "gauge",
+        "Bytes used in the accounting period",
+        |m| m.sum_bytes.map(|v| v as f64),
+    ),
+    (
+        "srun_sum_seconds",
+        "gauge",
+        "Online time in the accounting period",
+        |m| m.sum_seconds.map(|v| v as f64),
+    ),
+    (
+        "srun_remain_seconds",
+        "gauge",
+        "Remaining online time reported by the portal",
+        |m| m.remain_seconds.map(|v| v as f64),
+    ),
+    ("srun_user_balance", "gauge", "Account balance", |m| {
+        m.user_balance
+    }),
+    ("srun_wallet_balance", "gauge", "Wallet balance", |m| {
+        m.wallet_balance
+    }),
+    (
+        "srun_login_attempts_total",
+        "counter",
+        "Logins started by srun",
+        |m| Some(m.login_attempts as f64),
+    ),
+    (
+        "srun_login_failures_total",
+        "counter",
+        "Logins that failed after all retries",
+        |m| Some(m.login_failures as f64),
+    ),
+    (
+        "srun_login_latency_seconds",
+        "gauge",
+        "Duration of the last login",
+        |m| m.login_latency.map(|d| d.as_secs_f64()),
+    ),
+    (
+        "srun_last_refresh_timestamp_seconds",
+        "gauge",
+        "Unix time of the last refresh",
+        |m| Some(m.last_refresh as f64),
+    ),
+];
+
+impl Metrics {
+    /// Renders all users in the Prometheus text exposition format. Values the
+    /// portal did not report are left out.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, help, sample) in METRICS {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (user, metrics) in &self.users {
+                if let Some(value) = sample(metrics) {
+                    let _ = writeln!(out, "{}{{user=\"{}\"}} {}", name, escape(user), value);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `/metrics` from the shared [`Metrics`], one request per connection.
+pub struct MetricsServer {
+    listener: TcpListener,
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl MetricsServer {
+    pub fn bind(addr: &str, metrics: Arc<Mutex<Metrics>>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            metrics,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Runs the server on a background thread.
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                if let Err(e) = self.serve(stream) {
+                    debug!("Metrics connection closed: {}", e);
+                }
+            }
+        })
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut buffer = Vec::new();
+        let mut temp_buf = [0; 1024];
+        let path = loop {
+            let bytes_read = stream.read(&mut temp_buf)?;
+            if bytes_read == 0 {
+                return Ok(());
+            }
+            buffer.extend_from_slice(&temp_buf[..bytes_read]);
+            let mut headers = [httparse::EMPTY_HEADER; 32];
+            let mut request = httparse::Request::new(&mut headers);
+            if request.parse(&buffer)?.is_complete() {
+                break request.path.unwrap_or("/").to_string();
+            }
+        };
+
+        let (status, body) = match path.split('?').next() {
+            Some("/metrics") => ("200 OK", self.metrics.lock().unwrap().render()),
+            _ => ("404 Not Found", String::from("Not Found\n")),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics::default();
+        metrics.users.insert(
+            String::from("alice"),
+            UserMetrics {
+                up: true,
+                online: true,
+                bytes_in: Some(1024),
+                user_balance: Some(12.5),
+                login_attempts: 2,
+                login_latency: Some(Duration::from_millis(250)),
+                ..Default::default()
+            },
+        );
+        metrics
+            .users
+            .insert(String::from("b\"ob"), UserMetrics::default());
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE srun_login_attempts_total counter\n"));
+        assert!(text.contains("srun_online{user=\"alice\"} 1\n"));
+        assert!(text.contains("srun_session_bytes_in{user=\"alice\"} 1024\n"));
+        assert!(text.contains("srun_user_balance{user=\"alice\"} 12.5\n"));
+        assert!(text.contains("srun_login_latency_seconds{user=\"alice\"} 0.25\n"));
+        assert!(text.contains("srun_up{user=\"b\\\"ob\"} 0\n"));
+        assert!(!text.contains("srun_session_bytes_in{user=\"b\\\"ob\"}"));
+    }
+}
@@ -0,0 +1,622 @@
+use md5::{Digest, Md5};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::srun::{self, PasswordHash};
+use crate::xencode::{self, Encoding};
+use crate::{debug, info};
+
+const SRUN_VER: &str = "SRunCGIAuthIntfSvr V1.18 B20180306";
+const SYSVER: &str = "1.01.20180306";
+const CHALLENGE_EXPIRE: u64 = 60;
+
+/// Behaviour of the mock portal, including injected failures.
+#[derive(Debug, Clone, Default)]
+pub struct MockOptions {
+    /// Accounts accepted by the portal as (username, password)
+    pub accounts: Vec<(String, String)>,
+    /// Usernames rejected with an arrearage error
+    pub arrears: HashSet<String>,
+    /// Send responses with Transfer-Encoding: chunked
+    pub chunked: bool,
+    /// Delay before every response
+    pub delay: Duration,
+    /// Sessions allowed per username before login is rejected
+    pub max_online: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    username: String,
+    add_time: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+#[derive(Default)]
+struct MockState {
+    /// Challenge tokens by IP, with their issue time
+    challenges: HashMap<String, (String, u64)>,
+    /// Online sessions by IP
+    sessions: HashMap<String, Session>,
+    counter: u64,
+}
+
+/// Minimal Srun portal implementing `get_challenge`, `srun_portal`,
+/// `rad_user_info` and `rad_user_dm`, verifying `info`, `chksum` and `sign`
+/// like the real server.
+pub struct MockServer {
+    listener: TcpListener,
+    options: Arc<MockOptions>,
+    state: Arc<Mutex<MockState>>,
+    encoding: Arc<Encoding>,
+    password_hash: PasswordHash,
+}
+
+impl MockServer {
+    pub fn bind(addr: &str, config: &Config, options: MockOptions) -> Result<Self, Box<dyn Error>> {
+        let encoding = Encoding::new(
+            &config.enc,
+            config.enc_prefix.as_deref(),
+            config.enc_alphabet.as_deref(),
+        )?;
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            options: Arc::new(options),
+            state: Arc::new(Mutex::new(MockState::default())),
+            encoding: Arc::new(encoding),
+            password_hash: config.password_hash,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves connections until the listener fails, one thread per connection.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let handler = Handler {
+                options: self.options.clone(),
+                state: self.state.clone(),
+                encoding: self.encoding.clone(),
+                password_hash: self.password_hash,
+            };
+            thread::spawn(move || {
+                if let Err(e) = handler.serve(stream) {
+                    debug!("Mock connection closed: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Runs the server on a background thread.
+    #[cfg(test)]
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            if let Err(e) = self.run() {
+                crate::warn!("Mock server stopped: {}", e);
+            }
+        })
+    }
+}
+
+struct Handler {
+    options: Arc<MockOptions>,
+    state: Arc<Mutex<MockState>>,
+    encoding: Arc<Encoding>,
+    password_hash: PasswordHash,
+}
+
+impl Handler {
+    fn serve(&self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        let peer_ip = stream.peer_addr()?.ip().to_string();
+        let mut buffer = Vec::new();
+        let mut temp_buf = [0; 4096];
+        loop {
+            let bytes_read = stream.read(&mut temp_buf)?;
+            if bytes_read == 0 {
+                return Ok(());
+            }
+            buffer.extend_from_slice(&temp_buf[..bytes_read]);
+
+            // 处理缓冲区中所有完整的请求
+            loop {
+                let mut headers = [httparse::EMPTY_HEADER; 32];
+                let mut request = httparse::Request::new(&mut headers);
+                let n = match request.parse(&buffer)? {
+                    httparse::Status::Complete(n) => n,
+                    httparse::Status::Partial => break,
+                };
+                let target = request.path.unwrap_or("/").to_string();
+                buffer.drain(..n);
+
+                let (status, body) = self.handle(&target, &peer_ip);
+                thread::sleep(self.options.delay);
+                self.write_response(&mut stream, status, body.as_bytes())?;
+            }
+        }
+    }
+
+    fn handle(&self, target: &str, peer_ip: &str) -> (u16, String) {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        debug!("Mock request {} {:?}", path, query);
+
+        let body = match path {
+            "/cgi-bin/get_challenge" => self.get_challenge(&query, peer_ip),
+            "/cgi-bin/srun_portal" => match query.get("action").map(String::as_str) {
+                Some("login") => self.login(&query, peer_ip),
+                Some("logout") => self.logout(&query, peer_ip),
+                _ => error_response("action_error", "Unknown action", peer_ip),
+            },
+            "/cgi-bin/rad_user_info" => self.user_info(peer_ip),
+            "/cgi-bin/rad_user_dm" => self.dm(&query, peer_ip),
+            _ => return (404, String::from("Not Found")),
+        };
+
+        let body = body.to_string();
+        match query.get("callback") {
+            Some(callback) => (200, format!("{}({})", callback, body)),
+            None => (200, body),
+        }
+    }
+
+    fn get_challenge(&self, query: &HashMap<String, String>, peer_ip: &str) -> Value {
+        let ip = query.get("ip").map_or(peer_ip, String::as_str);
+        let mut state = self.state.lock().unwrap();
+        state.counter += 1;
+        let seed = format!("{}{}{}", ip, now(), state.counter);
+        let challenge = format!("{:x}", Md5::digest(seed.as_bytes())).repeat(2);
+        state
+            .challenges
+            .insert(ip.to_string(), (challenge.clone(), now()));
+        json!({
+            "challenge": challenge,
+            "client_ip": peer_ip,
+            "ecode": 0,
+            "error": "ok",
+            "error_msg": "",
+            "expire": CHALLENGE_EXPIRE.to_string(),
+            "online_ip": ip,
+            "res": "ok",
+            "srun_ver": SRUN_VER,
+            "st": now(),
+        })
+    }
+
+    fn login(&self, query: &HashMap<String, String>, peer_ip: &str) -> Value {
+        let param = |key: &str| query.get(key).map_or("", String::as_str);
+        let (username, ip) = (param("username"), param("ip"));
+
+        let mut state = self.state.lock().unwrap();
+        let challenge = match state.challenges.remove(ip) {
+            Some((challenge, issued)) if now() - issued <= CHALLENGE_EXPIRE => challenge,
+            _ => {
+                return error_response(
+                    "challenge_expire_error",
+                    "Challenge expired or missing",
+                    peer_ip,
+                );
+            }
+        };
+
+        let password = match self.options.accounts.iter().find(|(u, _)| u == username) {
+            Some((_, password)) => password,
+            None => return error_response("login_error", "E2531: User not found.", peer_ip),
+        };
+
+        // info 中携带明文密码，chksum 使用哈希后的密码
+        let info = match xencode::decode_param_i(param("info"), &self.encoding, &challenge) {
+            Ok(info) => info,
+            Err(_) => return error_response("sign_error", "Info decrypt failed", peer_ip),
+        };
+        let info: Value = serde_json::from_str(&info).unwrap_or_default();
+        if info["username"] != username
+            || info["ip"] != ip
+            || info["acid"] != param("ac_id").parse::<i64>().unwrap_or(-1)
+            || info["enc_ver"] != self.encoding.enc_ver.as_str()
+        {
+            return error_response("sign_error", "Info does not match request", peer_ip);
+        }
+        if info["password"] != password.as_str() {
+            return error_response("login_error", "E2553: Password is error.", peer_ip);
+        }
+
+        let (sent, hash) = match self.password_hash.hash(password, &challenge) {
+            Ok(hashed) => hashed,
+            Err(_) => return error_response("login_error", "Hash failed", peer_ip),
+        };
+        let fields = [
+            username,
+            &hash,
+            param("ac_id"),
+            ip,
+            param("n"),
+            param("type"),
+            param("info"),
+        ];
+        if param("password") != sent || param("chksum") != srun::check_sum(&challenge, &fields) {
+            return error_response("sign_error", "Checksum mismatch", peer_ip);
+        }
+
+        if self.options.arrears.contains(username) {
+            return error_response("login_error", "E2616: Arrearage users.", peer_ip);
+        }
+
+        if let Some(max_online) = self.options.max_online {
+            let online = state
+                .sessions
+                .iter()
+                .filter(|(session_ip, session)| {
+                    session.username == username && session_ip.as_str() != ip
+                })
+                .count();
+            if online >= max_online {
+                let mut response =
+                    error_response("login_error", "E2620: You are already online.", peer_ip);
+                response["ecode"] = json!("E2620");
+                return response;
+            }
+        }
+
+        state.sessions.insert(
+            ip.to_string(),
+            Session {
+                username: username.to_string(),
+                add_time: now(),
+                bytes_in: 0,
+                bytes_out: 0,
+            },
+        );
+        info!("Mock login: {} at {}", username, ip);
+        json!({
+            "ServerFlag": 0,
+            "ServicesIntfServerIP": "127.0.0.1",
+            "ServicesIntfServerPort": "8001",
+            "access_token": challenge,
+            "checkout_date": 0,
+            "client_ip": peer_ip,
+            "ecode": 0,
+            "error": "ok",
+            "error_msg": "",
+            "online_ip": ip,
+            "ploy_msg": "E0000: Login is successful.",
+            "real_name": "",
+            "remain_flux": 0,
+            "remain_times": 0,
+            "res": "ok",
+            "srun_ver": SRUN_VER,
+            "suc_msg": "login_ok",
+            "sysver": SYSVER,
+            "username": username,
+            "wallet_balance": 0,
+        })
+    }
+
+    fn logout(&self, query: &HashMap<String, String>, peer_ip: &str) -> Value {
+        let ip = query.get("ip").map_or(peer_ip, String::as_str);
+        let username = query.get("username").map_or("", String::as_str);
+        let mut state = self.state.lock().unwrap();
+        match state.sessions.get(ip) {
+            Some(session) if session.username == username => {
+                state.sessions.remove(ip);
+                info!("Mock logout: {} at {}", username, ip);
+                json!({
+                    "client_ip": peer_ip,
+                    "ecode": 0,
+                    "error": "ok",
+                    "error_msg": "",
+                    "online_ip": ip,
+                    "res": "ok",
+                    "srun_ver": SRUN_VER,
+                    "st": now(),
+                    "suc_msg": "logout_ok",
+                })
+            }
+            _ => error_response("not_online_error", "", peer_ip),
+        }
+    }
+
+    /// Drops the session at `ip`, or all sessions of the user if `ip` is empty.
+    fn dm(&self, query: &HashMap<String, String>, peer_ip: &str) -> Value {
+        let param = |key: &str| query.get(key).map_or("", String::as_str);
+        let (username, ip, time) = (param("username"), param("ip"), param("time"));
+        if param("sign") != srun::dm_sign(time, username, ip, param("unbind")) {
+            return error_response("sign_error", "Sign mismatch", peer_ip);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let mut closed: Vec<String> = state
+            .sessions
+            .iter()
+            .filter(|(session_ip, session)| {
+                session.username == username && (ip.is_empty() || session_ip.as_str() == ip)
+            })
+            .map(|(session_ip, _)| session_ip.clone())
+            .collect();
+        if closed.is_empty() {
+            return error_response("not_online_error", "", peer_ip);
+        }
+        closed.sort();
+        for session_ip in &closed {
+            state.sessions.remove(session_ip);
+        }
+        info!("Mock DM: {} at {}", username, closed.join(","));
+        json!({
+            "client_ip": peer_ip,
+            "ecode": 0,
+            "error": "logout_ok",
+            "error_msg": "",
+            "online_ip": closed.join(","),
+            "res": "ok",
+            "srun_ver": SRUN_VER,
+            "st": now(),
+        })
+    }
+
+    fn user_info(&self, peer_ip: &str) -> Value {
+        let mut state = self.state.lock().unwrap();
+        match state.sessions.get_mut(peer_ip) {
+            Some(session) => {
+                // 每次查询模拟一些流量
+                session.bytes_in += 1_572_864;
+                session.bytes_out += 262_144;
+                let seconds = now() - session.add_time;
+                json!({
+                    "ServerFlag": 0,
+                    "add_time": session.add_time,
+                    "all_bytes": session.bytes_in + session.bytes_out,
+                    "bytes_in": session.bytes_in,
+                    "bytes_out": session.bytes_out,
+                    "checkout_date": 0,
+                    "domain": "",
+                    "error": "ok",
+                    "keepalive_time": now(),
+                    "online_ip": peer_ip,
+                    "products_id": "1",
+                    "real_name": "",
+                    "remain_bytes": 0,
+                    "remain_seconds": 0,
+                    "sum_bytes": session.bytes_in + session.bytes_out,
+                    "sum_seconds": seconds,
+                    "sysver": SYSVER,
+                    "user_balance": 10,
+                    "user_charge": 0,
+                    "user_mac": "00:00:00:00:00:00",
+                    "user_name": session.username,
+                    "wallet_balance": 0,
+                })
+            }
+            None => error_response("not_online_error", "", peer_ip),
+        }
+    }
+
+    fn write_response(
+        &self,
+        stream: &mut TcpStream,
+        status: u16,
+        body: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let reason = if status == 200 { "OK" } else { "Not Found" };
+        let mut response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: text/html; charset=UTF-8\r\nConnection: keep-alive\r\n",
+            status, reason
+        )
+        .into_bytes();
+        if self.options.chunked {
+            response.extend_from_slice(b"Transfer-Encoding: chunked\r\n\r\n");
+            for chunk in body.chunks(16) {
+                response.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                response.extend_from_slice(chunk);
+                response.extend_from_slice(b"\r\n");
+            }
+            response.extend_from_slice(b"0\r\n\r\n");
+        } else {
+            response
+                .extend_from_slice(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+            response.extend_from_slice(body);
+        }
+        stream.write_all(&response)?;
+        Ok(())
+    }
+}
+
+fn error_response(error: &str, error_msg: &str, ip: &str) -> Value {
+    json!({
+        "client_ip": ip,
+        "ecode": 0,
+        "error": error,
+        "error_msg": error_msg,
+        "online_ip": ip,
+        "res": error,
+        "srun_ver": SRUN_VER,
+        "st": now(),
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::User;
+    use crate::srun::SrunClient;
+
+    fn start(options: MockOptions) -> Result<Config, Box<dyn Error>> {
+        let mut config = Config {
+            retry_count: 1,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        let server = MockServer::bind("127.0.0.1:0", &config, options)?;
+        config.server = format!("http://127.0.0.1:{}", server.local_addr()?.port());
+        server.spawn();
+        Ok(config)
+    }
+
+    fn user(username: &str, password: &str) -> User {
+        user_at(username, password, "127.0.0.1")
+    }
+
+    fn user_at(username: &str, password: &str, ip: &str) -> User {
+        User {
+            username: username.into(),
+            password: password.into(),
+            ip: Some(ip.to_string()),
+            iface: None,
+            mac: None,
+            dhcp: false,
+            thresholds: None,
+            bind_addr: Some(ip.parse().unwrap()),
+        }
+    }
+
+    fn accounts() -> Vec<(String, String)> {
+        vec![
+            (String::from("alice"), String::from("secret")),
+            (String::from("bob"), String::from("hunter2")),
+        ]
+    }
+
+    #[test]
+    fn test_login_status_logout() -> Result<(), Box<dyn Error>> {
+        let config = start(MockOptions {
+            accounts: accounts(),
+            ..Default::default()
+        })?;
+        let mut client = SrunClient::new(&config, user("alice", "secret"))?;
+
+        let (online, _) = client.check_status()?;
+        assert!(!online);
+        client.login()?;
+        let (online, info) = client.check_status()?;
+        assert!(online);
+        assert_eq!(info.user_name.as_deref(), Some("alice"));
+        client.logout()?;
+        let (online, _) = client.check_status()?;
+        assert!(!online);
+        Ok(())
+    }
+
+    #[test]
+    fn test_login_failures() -> Result<(), Box<dyn Error>> {
+        let config = start(MockOptions {
+            accounts: accounts(),
+            arrears: HashSet::from([String::from("bob")]),
+            ..Default::default()
+        })?;
+
+        let mut client = SrunClient::new(&config, user("alice", "wrong"))?;
+        assert!(client.login().is_err());
+        let mut client = SrunClient::new(&config, user("bob", "hunter2"))?;
+        assert!(client.login().is_err());
+        let mut client = SrunClient::new(&config, user("carol", "secret"))?;
+        assert!(client.login().is_err());
+        let (online, _) = client.check_status()?;
+        assert!(!online);
+        Ok(())
+    }
+
+    #[test]
+    fn test_login_chunked_and_delayed() -> Result<(), Box<dyn Error>> {
+        let config = start(MockOptions {
+            accounts: accounts(),
+            chunked: true,
+            delay: Duration::from_millis(20),
+            ..Default::default()
+        })?;
+        let mut client = SrunClient::new(&config, user("bob", "hunter2"))?;
+        client.login()?;
+        let (online, info) = client.check_status()?;
+        assert!(online);
+        assert_eq!(info.online_ip, "127.0.0.1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_login_other_encoding() -> Result<(), Box<dyn Error>> {
+        let mut config = start(MockOptions {
+            accounts: accounts(),
+            ..Default::default()
+        })?;
+        // The mock keeps verifying srun_bx1, so a different alphabet must be rejected
+        config.enc_alphabet = Some(String::from("standard"));
+        let mut client = SrunClient::new(&config, user("alice", "secret"))?;
+        assert!(client.login().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kick_and_auto_kick() -> Result<(), Box<dyn Error>> {
+        let mut config = start(MockOptions {
+            accounts: accounts(),
+            max_online: Some(1),
+            ..Default::default()
+        })?;
+        // Sessions are keyed by the ip parameter, so other addresses need not exist
+        let mut other = SrunClient::new(&config, user_at("alice", "secret", "10.0.0.2"))?;
+        other.login()?;
+        let mut client = SrunClient::new(&config, user("alice", "secret"))?;
+        assert!(client.login().is_err());
+
+        assert_eq!(client.kick("10.0.0.2")?, ["10.0.0.2"]);
+        assert!(client.kick("10.0.0.2").is_err());
+        client.login()?;
+        assert!(client.check_status()?.0);
+
+        // Drops the first listed session that is online, then retries
+        // without using up an attempt
+        config.auto_kick = true;
+        config.auto_kick_ips = vec![String::from("10.0.0.9"), String::from("127.0.0.1")];
+        config.retry_count = 1;
+        let mut other = SrunClient::new(&config, user_at("alice", "secret", "10.0.0.2"))?;
+        other.login()?;
+        assert!(!client.check_status()?.0);
+
+        // A failed kick falls through to the normal retries
+        config.auto_kick_ips = vec![String::from("10.0.0.9")];
+        let mut client = SrunClient::new(&config, user("alice", "secret"))?;
+        assert!(client.login().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_logout_remote_and_all() -> Result<(), Box<dyn Error>> {
+        let config = start(MockOptions {
+            accounts: accounts(),
+            ..Default::default()
+        })?;
+        for ip in ["10.0.0.2", "10.0.0.3", "127.0.0.1"] {
+            SrunClient::new(&config, user_at("alice", "secret", ip))?.login()?;
+        }
+        SrunClient::new(&config, user_at("bob", "hunter2", "10.0.0.4"))?.login()?;
+
+        let mut client = SrunClient::new(&config, user("alice", "secret"))?;
+        client.logout_ip("10.0.0.2")?;
+        assert!(client.logout_ip("10.0.0.2").is_err());
+        assert!(client.logout_ip("10.0.0.4").is_err());
+        assert_eq!(client.kick("")?, ["10.0.0.3", "127.0.0.1"]);
+        assert!(!client.check_status()?.0);
+
+        let mut client = SrunClient::new(&config, user("bob", "hunter2"))?;
+        assert_eq!(client.kick("")?, ["10.0.0.4"]);
+        Ok(())
+    }
+}
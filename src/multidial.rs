@@ -0,0 +1,281 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+use crate::config::{Config, User};
+use crate::error::ConfigError;
+use crate::mac;
+
+#[cfg(target_os = "linux")]
+use crate::config;
+#[cfg(target_os = "linux")]
+use crate::netlink;
+#[cfg(target_os = "linux")]
+use crate::{info, warn};
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant};
+
+/// Macvlan interfaces on `parent` for the users that have neither `iface`
+/// nor `ip`, named `prefix` followed by a hash of the username so that the
+/// names stay put when users are added or removed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Multidial {
+    /// Parent interface, e.g. `eth0`
+    pub parent: String,
+    pub prefix: String,
+    /// Seconds to wait for a new interface to get an IPv4 address
+    pub address_timeout: u64,
+    /// Obtain the addresses with the built-in DHCP client instead of waiting
+    pub dhcp: bool,
+}
+
+impl Default for Multidial {
+    fn default() -> Self {
+        Self {
+            parent: String::new(),
+            prefix: String::from("srun"),
+            address_timeout: 30,
+            dhcp: false,
+        }
+    }
+}
+
+impl Multidial {
+    /// The interface name and MAC address of every user dialing through a
+    /// macvlan, by index into `users`. The MAC address is the user's `mac`,
+    /// or one derived from the username.
+    pub fn plan(&self, users: &[User]) -> Vec<(usize, String, [u8; 6])> {
+        users
+            .iter()
+            .enumerate()
+            .filter(|(_, user)| user.iface.is_none() && user.ip.is_none())
+            .map(|(i, user)| {
+                let mac = user.mac.as_deref().and_then(mac::parse);
+                let mac = mac.unwrap_or_else(|| mac::derive(&user.username));
+                (i, self.name(&user.username), mac)
+            })
+            .collect()
+    }
+
+    /// The interface name of a user, which must fit in the 15 bytes Linux
+    /// allows.
+    fn name(&self, username: &str) -> String {
+        let digest = mac::derive(username);
+        format!(
+            "{}{:02x}{:02x}{:02x}",
+            self.prefix, digest[3], digest[4], digest[5]
+        )
+    }
+
+    /// Sets `iface` of the users dialing through a macvlan and returns their
+    /// indices into `users`.
+    pub fn assign(&self, users: &mut [User]) -> Result<Vec<usize>, ConfigError> {
+        if self.parent.is_empty() {
+            return Err(ConfigError::Validation(
+                "multidial requires a parent interface",
+            ));
+        }
+        if self.prefix.len() > 9 {
+            return Err(ConfigError::Validation(
+                "multidial prefix cannot be longer than 9 bytes",
+            ));
+        }
+        let plan = self.plan(users);
+        for (n, (_, name, _)) in plan.iter().enumerate() {
+            if plan[..n].iter().any(|(_, other, _)| other == name) {
+                return Err(ConfigError::Validation(
+                    "Two multidial users hash to the same interface name",
+                ));
+            }
+        }
+        Ok(plan
+            .into_iter()
+            .map(|(i, name, _)| {
+                users[i].iface = Some(name);
+                i
+            })
+            .collect())
+    }
+}
+
+/// The macvlan interfaces created by srun, deleted when dropped. Interfaces
+/// that already existed with the planned MAC address are left alone.
+#[derive(Debug, Default)]
+pub struct Macvlans {
+    #[cfg(target_os = "linux")]
+    created: Vec<String>,
+}
+
+impl Macvlans {
+    /// Creates the missing interfaces of the `multidial` section, replacing
+    /// those with another MAC address, and waits for their addresses.
+    #[cfg(target_os = "linux")]
+    pub fn ensure(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        let Some(multidial) = &config.multidial else {
+            return Ok(());
+        };
+        if multidial.parent.is_empty() {
+            return Err(ConfigError::Validation("multidial requires a parent interface").into());
+        }
+
+        let mut new = Vec::new();
+        for (i, name, mac) in multidial.plan(&config.users) {
+            let username = &config.users[i].username;
+            match netlink::mac_address(&name) {
+                Some(current) if current == mac => {}
+                current => {
+                    if current.is_some() {
+                        info!("Replacing interface {} with another MAC address", name);
+                        netlink::delete_link(&name)?;
+                    }
+                    netlink::create_macvlan(&name, &multidial.parent, mac)
+                        .map_err(|e| format!("Failed to create macvlan {}: {}", name, e))?;
+                    info!(
+                        "Created {} on {} with MAC {} for user {}",
+                        name,
+                        multidial.parent,
+                        mac::format(&mac),
+                        username
+                    );
+                    if !self.created.contains(&name) {
+                        self.created.push(name.clone());
+                    }
+                    new.push(name);
+                }
+            }
+        }
+
+        if multidial.dhcp {
+            return Ok(());
+        }
+        let deadline = Instant::now() + Duration::from_secs(multidial.address_timeout);
+        for name in new {
+            while config::iface_ipv4(&name).is_err() {
+                if Instant::now() >= deadline {
+                    warn!(
+                        "No IPv4 address on {} yet, make sure a DHCP client configures it",
+                        name
+                    );
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn ensure(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        match config.multidial {
+            Some(_) => Err("multidial is only supported on Linux".into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Macvlans {
+    fn drop(&mut self) {
+        for name in self.created.drain(..) {
+            match netlink::delete_link(&name) {
+                Ok(()) => info!("Removed interface {}", name),
+                Err(e) => warn!("Failed to remove interface {}: {}", name, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(username: &str, iface: Option<&str>) -> User {
+        User {
+            username: username.into(),
+            password: String::from("secret"),
+            ip: None,
+            iface: iface.map(String::from),
+            mac: None,
+            dhcp: false,
+            thresholds: None,
+            bind_addr: None,
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let multidial = Multidial {
+            parent: String::from("eth0"),
+            ..Default::default()
+        };
+        let mut users = vec![
+            user("alice", None),
+            user("bob", Some("eth1")),
+            user("carol", None),
+        ];
+        users[2].mac = Some(String::from("02:00:00:00:00:01"));
+        let macs: Vec<_> = multidial
+            .plan(&users)
+            .into_iter()
+            .map(|(.., mac)| mac)
+            .collect();
+        assert_eq!(macs, [mac::derive("alice"), [2, 0, 0, 0, 0, 1]]);
+
+        let mut without_alice = users[1..].to_vec();
+        assert_eq!(multidial.assign(&mut users).unwrap(), [0, 2]);
+        let ifaces: Vec<_> = users.iter().map(|user| user.iface.as_deref()).collect();
+        assert_eq!(
+            ifaces,
+            [Some("srun6a356b"), Some("eth1"), Some("srun56ee64")]
+        );
+        // 删除用户不会改变其他用户的接口名
+        multidial.assign(&mut without_alice).unwrap();
+        assert_eq!(without_alice[1].iface.as_deref(), Some("srun56ee64"));
+        assert!(Multidial::default().assign(&mut users).is_err());
+        let long = Multidial {
+            prefix: String::from("srun-macvlan"),
+            ..multidial
+        };
+        assert!(long.assign(&mut without_alice).is_err());
+    }
+
+    /// Needs CAP_NET_ADMIN, e.g. `sudo unshare -n cargo test -- --ignored`
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore]
+    fn test_macvlans() -> Result<(), Box<dyn Error>> {
+        use std::process::Command;
+
+        let status = Command::new("ip")
+            .args([
+                "link", "add", "srtest-a", "type", "veth", "peer", "name", "srtest-b",
+            ])
+            .status()?;
+        assert!(status.success());
+        let config = Config {
+            users: vec![user("alice", None), user("bob", None)],
+            multidial: Some(Multidial {
+                parent: String::from("srtest-a"),
+                prefix: String::from("srtest"),
+                address_timeout: 0,
+                dhcp: false,
+            }),
+            ..Default::default()
+        };
+        // 已存在且 MAC 相同的接口不归 srun 管理
+        netlink::create_macvlan("srtest6a356b", "srtest-a", mac::derive("alice"))?;
+        let mut macvlans = Macvlans::default();
+        macvlans.ensure(&config)?;
+        macvlans.ensure(&config)?;
+        assert_eq!(macvlans.created, ["srtestcd22b3"]);
+        assert_eq!(
+            netlink::mac_address("srtestcd22b3"),
+            Some(mac::derive("bob"))
+        );
+        drop(macvlans);
+        assert_eq!(netlink::mac_address("srtestcd22b3"), None);
+        assert!(netlink::mac_address("srtest6a356b").is_some());
+        netlink::delete_link("srtest-a")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,446 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::warn;
+
+const HEADER_LEN: usize = mem::size_of::<libc::nlmsghdr>();
+/// Size of `struct ifinfomsg`
+const IFINFO_LEN: usize = 16;
+/// From `linux/if_link.h`, not exported by libc
+const IFLA_MACVLAN_MODE: u16 = 1;
+const MACVLAN_MODE_BRIDGE: u32 = 4;
+/// From `linux/fib_rules.h` and `linux/rtnetlink.h`, not exported by libc
+const FRA_SRC: u16 = 2;
+const FRA_TABLE: u16 = 15;
+const FR_ACT_TO_TBL: u8 = 1;
+const RTPROT_DHCP: u8 = 16;
+
+/// Opens a route netlink socket subscribed to `groups`.
+fn open(groups: u32) -> io::Result<OwnedFd> {
+    // SAFETY: plain socket call, the descriptor is owned right after
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a freshly created descriptor not owned elsewhere
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: sockaddr_nl is plain data, all zeroes is valid
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    addr.nl_groups = groups;
+    // SAFETY: addr is a valid sockaddr_nl of the given length
+    let ret = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+fn recv(socket: &OwnedFd, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        // SAFETY: buf is valid for writes of its length
+        let n = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Creates a macvlan interface in bridge mode on `parent` and brings it up.
+pub fn create_macvlan(name: &str, parent: &str, mac: [u8; 6]) -> io::Result<()> {
+    let parent = index(parent)?;
+    let up = libc::IFF_UP as u32;
+    let mut message = link_message(
+        libc::RTM_NEWLINK,
+        libc::NLM_F_CREATE | libc::NLM_F_EXCL,
+        0,
+        up,
+        up,
+    );
+    let name = CString::new(name).map_err(io::Error::other)?;
+    push_attr(&mut message, libc::IFLA_IFNAME, name.as_bytes_with_nul());
+    push_attr(&mut message, libc::IFLA_LINK, &parent.to_ne_bytes());
+    push_attr(&mut message, libc::IFLA_ADDRESS, &mac);
+    let linkinfo = begin_nested(&mut message, libc::IFLA_LINKINFO);
+    push_attr(&mut message, libc::IFLA_INFO_KIND, b"macvlan");
+    let data = begin_nested(&mut message, libc::IFLA_INFO_DATA);
+    push_attr(
+        &mut message,
+        IFLA_MACVLAN_MODE,
+        &MACVLAN_MODE_BRIDGE.to_ne_bytes(),
+    );
+    end_nested(&mut message, data);
+    end_nested(&mut message, linkinfo);
+    request(message)
+}
+
+/// Deletes the interface `name`.
+pub fn delete_link(name: &str) -> io::Result<()> {
+    let message = link_message(libc::RTM_DELLINK, 0, index(name)?, 0, 0);
+    request(message)
+}
+
+/// Sets the hardware address of the interface `name`, taking it down for
+/// the change if its driver cannot change the address while up. A link that
+/// was down stays down.
+pub fn set_mac_address(name: &str, mac: [u8; 6]) -> io::Result<()> {
+    let index = index(name)?;
+    let set_address = || {
+        let mut message = link_message(libc::RTM_NEWLINK, 0, index, 0, 0);
+        push_attr(&mut message, libc::IFLA_ADDRESS, &mac);
+        request(message)
+    };
+    match set_address() {
+        Err(e) if e.raw_os_error() == Some(libc::EBUSY) && is_up(name) => {
+            let up = libc::IFF_UP as u32;
+            request(link_message(libc::RTM_NEWLINK, 0, index, 0, up))?;
+            let result = set_address();
+            request(link_message(libc::RTM_NEWLINK, 0, index, up, up))?;
+            result
+        }
+        result => result,
+    }
+}
+
+/// The index of the interface `name`.
+pub fn index(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name).map_err(io::Error::other)?;
+    // SAFETY: c_name is NUL-terminated
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Network interface {} not found", name),
+        )),
+        index => Ok(index),
+    }
+}
+
+/// A request with its fixed-size `body`, attributes are appended after it.
+fn message(kind: u16, flags: libc::c_int, body: &[u8]) -> Vec<u8> {
+    let flags = (libc::NLM_F_REQUEST | libc::NLM_F_ACK | flags) as u16;
+    let mut message = Vec::with_capacity(128);
+    message.extend_from_slice(&0u32.to_ne_bytes()); // length, set by request
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(&flags.to_ne_bytes());
+    message.extend_from_slice(&1u32.to_ne_bytes()); // sequence
+    message.extend_from_slice(&0u32.to_ne_bytes()); // port, the kernel fills it in
+    message.extend_from_slice(body);
+    message
+}
+
+/// A link message changing the `change` bits of the interface flags to those
+/// of `ifi_flags`.
+fn link_message(kind: u16, flags: libc::c_int, index: u32, ifi_flags: u32, change: u32) -> Vec<u8> {
+    // struct ifinfomsg { family, pad: u8, type: u16, index: i32, flags, change: u32 }
+    let mut body = Vec::with_capacity(IFINFO_LEN);
+    body.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    body.extend_from_slice(&index.to_ne_bytes());
+    body.extend_from_slice(&ifi_flags.to_ne_bytes());
+    body.extend_from_slice(&change.to_ne_bytes());
+    message(kind, flags, &body)
+}
+
+fn addr_message(kind: u16, flags: libc::c_int, index: u32, addr: Ipv4Addr, prefix: u8) -> Vec<u8> {
+    // struct ifaddrmsg { family, prefixlen, flags, scope: u8, index: u32 }
+    let mut body = vec![libc::AF_INET as u8, prefix, 0, libc::RT_SCOPE_UNIVERSE];
+    body.extend_from_slice(&index.to_ne_bytes());
+    let mut message = message(kind, flags, &body);
+    push_attr(&mut message, libc::IFA_LOCAL, &addr.octets());
+    push_attr(&mut message, libc::IFA_ADDRESS, &addr.octets());
+    message
+}
+
+/// Adds `addr/prefix` to the interface `name`, or updates its lifetime. The
+/// kernel removes the address after `lifetime` seconds, `u32::MAX` keeps it.
+pub fn add_address(name: &str, addr: Ipv4Addr, prefix: u8, lifetime: u32) -> io::Result<()> {
+    let flags = libc::NLM_F_CREATE | libc::NLM_F_REPLACE;
+    let mut message = addr_message(libc::RTM_NEWADDR, flags, index(name)?, addr, prefix);
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    let broadcast = Ipv4Addr::from(u32::from(addr) | !mask);
+    push_attr(&mut message, libc::IFA_BROADCAST, &broadcast.octets());
+    // struct ifa_cacheinfo { preferred, valid, cstamp, tstamp: u32 }
+    let mut cacheinfo = Vec::with_capacity(16);
+    cacheinfo.extend_from_slice(&lifetime.to_ne_bytes());
+    cacheinfo.extend_from_slice(&lifetime.to_ne_bytes());
+    cacheinfo.extend_from_slice(&[0; 8]);
+    push_attr(&mut message, libc::IFA_CACHEINFO, &cacheinfo);
+    request(message)
+}
+
+/// Sends everything from the source `addr` through `gateway` on the interface
+/// `name`: a default route in the routing table `table` and a rule looking up
+/// that table for the source. Sockets bound to the address thus leave through
+/// the interface whatever the main table routes elsewhere.
+pub fn add_source_route(
+    name: &str,
+    addr: Ipv4Addr,
+    gateway: Ipv4Addr,
+    table: u32,
+) -> io::Result<()> {
+    // struct rtmsg { family, dst_len, src_len, tos, table, protocol, scope, type: u8, flags: u32 }
+    let body = [
+        libc::AF_INET as u8,
+        0,
+        0,
+        0,
+        libc::RT_TABLE_UNSPEC,
+        RTPROT_DHCP,
+        libc::RT_SCOPE_UNIVERSE,
+        libc::RTN_UNICAST,
+        0,
+        0,
+        0,
+        0,
+    ];
+    let flags = libc::NLM_F_CREATE | libc::NLM_F_REPLACE;
+    let mut message = message(libc::RTM_NEWROUTE, flags, &body);
+    push_attr(&mut message, libc::RTA_TABLE, &table.to_ne_bytes());
+    push_attr(&mut message, libc::RTA_GATEWAY, &gateway.octets());
+    push_attr(&mut message, libc::RTA_OIF, &index(name)?.to_ne_bytes());
+    request(message)?;
+    let flags = libc::NLM_F_CREATE | libc::NLM_F_EXCL;
+    match request(rule_message(libc::RTM_NEWRULE, flags, addr, table)) {
+        Err(e) if e.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+        result => result,
+    }
+}
+
+/// Removes the rule `add_source_route` added for the source `addr`.
+pub fn delete_source_rule(addr: Ipv4Addr, table: u32) -> io::Result<()> {
+    request(rule_message(libc::RTM_DELRULE, 0, addr, table))
+}
+
+fn rule_message(kind: u16, flags: libc::c_int, src: Ipv4Addr, table: u32) -> Vec<u8> {
+    // struct fib_rule_hdr { family, dst_len, src_len, tos, table, res1, res2, action: u8, flags: u32 }
+    let body = [
+        libc::AF_INET as u8,
+        0,
+        32,
+        0,
+        libc::RT_TABLE_UNSPEC,
+        0,
+        0,
+        FR_ACT_TO_TBL,
+        0,
+        0,
+        0,
+        0,
+    ];
+    let mut message = message(kind, flags, &body);
+    push_attr(&mut message, FRA_SRC, &src.octets());
+    push_attr(&mut message, FRA_TABLE, &table.to_ne_bytes());
+    message
+}
+
+/// Removes `addr/prefix` from the interface `name`.
+pub fn delete_address(name: &str, addr: Ipv4Addr, prefix: u8) -> io::Result<()> {
+    let message = addr_message(libc::RTM_DELADDR, 0, index(name)?, addr, prefix);
+    request(message)
+}
+
+/// Whether the interface `name` is administratively up.
+fn is_up(name: &str) -> bool {
+    std::fs::read_to_string(format!("/sys/class/net/{}/flags", name))
+        .ok()
+        .and_then(|flags| u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+        .is_some_and(|flags| flags & libc::IFF_UP as u32 != 0)
+}
+
+/// The MAC address of the interface `name`.
+pub fn mac_address(name: &str) -> Option<[u8; 6]> {
+    let address = std::fs::read_to_string(format!("/sys/class/net/{}/address", name)).ok()?;
+    let octets: Vec<u8> = address
+        .trim()
+        .split(':')
+        .map(|octet| u8::from_str_radix(octet, 16))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    octets.try_into().ok()
+}
+
+fn push_attr(message: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    let len = (4 + data.len()) as u16;
+    message.extend_from_slice(&len.to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(data);
+    message.resize(message.len().next_multiple_of(4), 0);
+}
+
+fn begin_nested(message: &mut Vec<u8>, kind: u16) -> usize {
+    let start = message.len();
+    push_attr(message, kind | libc::NLA_F_NESTED as u16, &[]);
+    start
+}
+
+fn end_nested(message: &mut [u8], start: usize) {
+    let len = (message.len() - start) as u16;
+    message[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+}
+
+/// Sends `message` to the kernel and waits for its acknowledgement.
+fn request(mut message: Vec<u8>) -> io::Result<()> {
+    let len = message.len() as u32;
+    message[0..4].copy_from_slice(&len.to_ne_bytes());
+    let socket = open(0)?;
+    // SAFETY: message is valid for reads of its length
+    let sent = unsafe {
+        libc::send(
+            socket.as_raw_fd(),
+            message.as_ptr() as *const libc::c_void,
+            message.len(),
+            0,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut buf = vec![0u8; 8192];
+    loop {
+        let n = recv(&socket, &mut buf)?;
+        if let Some(errno) = ack(&buf[..n]) {
+            return match errno {
+                0 => Ok(()),
+                errno => Err(io::Error::from_raw_os_error(-errno)),
+            };
+        }
+    }
+}
+
+/// The error code of the acknowledgement in a netlink datagram, 0 on success.
+fn ack(buf: &[u8]) -> Option<i32> {
+    let mut buf = buf;
+    while buf.len() >= HEADER_LEN {
+        let len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+        let kind = u16::from_ne_bytes(buf[4..6].try_into().unwrap());
+        if len < HEADER_LEN || len > buf.len() {
+            break;
+        }
+        if kind == libc::NLMSG_ERROR as u16 && len >= HEADER_LEN + 4 {
+            let error = &buf[HEADER_LEN..HEADER_LEN + 4];
+            return Some(i32::from_ne_bytes(error.try_into().unwrap()));
+        }
+        buf = &buf[len.next_multiple_of(4).min(buf.len())..];
+    }
+    None
+}
+
+/// Subscribes to IPv4 address changes and sends the name of every interface
+/// whose address was added or removed.
+pub fn address_changes() -> io::Result<Receiver<String>> {
+    let socket = open(libc::RTMGRP_IPV4_IFADDR as u32)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = vec![0u8; 16384];
+        loop {
+            let n = match recv(&socket, &mut buf) {
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("Netlink socket failed, no longer watching addresses: {}", e);
+                    return;
+                }
+            };
+            for index in changed_interfaces(&buf[..n]) {
+                if let Some(name) = interface_name(index)
+                    && sender.send(name).is_err()
+                {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+/// Interface indexes of the address messages in a netlink datagram.
+fn changed_interfaces(mut buf: &[u8]) -> Vec<u32> {
+    let mut indexes = Vec::new();
+    while buf.len() >= HEADER_LEN {
+        let len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+        let kind = u16::from_ne_bytes(buf[4..6].try_into().unwrap());
+        if len < HEADER_LEN || len > buf.len() {
+            break;
+        }
+        // struct ifaddrmsg { family, prefixlen, flags, scope: u8, index: u32 }
+        if (kind == libc::RTM_NEWADDR || kind == libc::RTM_DELADDR) && len >= HEADER_LEN + 8 {
+            let index = &buf[HEADER_LEN + 4..HEADER_LEN + 8];
+            indexes.push(u32::from_ne_bytes(index.try_into().unwrap()));
+        }
+        buf = &buf[len.next_multiple_of(4).min(buf.len())..];
+    }
+    indexes
+}
+
+fn interface_name(index: u32) -> Option<String> {
+    let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: name has room for IF_NAMESIZE bytes as required
+    let ret = unsafe { libc::if_indextoname(index, name.as_mut_ptr()) };
+    if ret.is_null() {
+        return None;
+    }
+    // SAFETY: if_indextoname wrote a NUL-terminated string
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let len = (HEADER_LEN + payload.len()) as u32;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&len.to_ne_bytes());
+        buf.extend_from_slice(&kind.to_ne_bytes());
+        buf.resize(HEADER_LEN, 0);
+        buf.extend_from_slice(payload);
+        buf.resize(buf.len().next_multiple_of(4), 0);
+        buf
+    }
+
+    #[test]
+    fn test_changed_interfaces() {
+        let mut ifaddr = vec![libc::AF_INET as u8, 24, 0, 0];
+        ifaddr.extend_from_slice(&7u32.to_ne_bytes());
+        // IFA_ADDRESS attribute, length not a multiple of 4 is padded
+        ifaddr.extend_from_slice(&[8, 0, 1, 0, 10, 0, 0, 2, 1]);
+
+        let mut buf = message(libc::RTM_NEWADDR, &ifaddr);
+        buf.extend(message(libc::RTM_NEWLINK, &[0; 16]));
+        ifaddr[4..8].copy_from_slice(&3u32.to_ne_bytes());
+        buf.extend(message(libc::RTM_DELADDR, &ifaddr));
+        assert_eq!(changed_interfaces(&buf), [7, 3]);
+
+        assert!(changed_interfaces(&buf[..HEADER_LEN + 2]).is_empty());
+        assert_eq!(interface_name(u32::MAX), None);
+    }
+}
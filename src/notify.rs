@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::error::Error;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::{ParseError, Url};
+
+use crate::config::Config;
+use crate::hooks::{Event, EventInfo, Hooks};
+use crate::http::{CertVerification, HttpClient};
+use crate::srun::InfoResponse;
+use crate::state::Journal;
+use crate::{debug, warn};
+
+/// Webhook URLs receiving a JSON POST for every event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Webhooks {
+    pub urls: Vec<String>,
+    /// Event names to send, e.g. `login_failed`; all events if empty
+    pub events: Vec<String>,
+    /// Additional attempts after a failed delivery
+    pub retries: u32,
+    /// Delay between attempts in milliseconds
+    pub retry_delay: u64,
+    /// Minimum seconds between notifications of the same event for a user
+    pub min_interval: u64,
+    /// Request timeout in milliseconds
+    pub timeout: u64,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Self {
+            urls: vec![],
+            events: vec![],
+            retries: 3,
+            retry_delay: 2000,
+            min_interval: 300,
+            timeout: 5000,
+        }
+    }
+}
+
+impl Webhooks {
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+    }
+}
+
+/// Delivers events to the configured webhooks in the background.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    webhooks: Webhooks,
+    cert_verification: CertVerification,
+}
+
+impl Notifier {
+    pub fn new(webhooks: &Webhooks, cert_verification: &CertVerification) -> Self {
+        Self {
+            webhooks: webhooks.clone(),
+            cert_verification: cert_verification.clone(),
+        }
+    }
+
+    /// The rate limit of `event`, `None` if it is filtered out.
+    pub fn min_interval(&self, event: Event) -> Option<u64> {
+        if self.webhooks.urls.is_empty()
+            || !(self.webhooks.events.is_empty()
+                || self.webhooks.events.iter().any(|name| name == event.name()))
+        {
+            return None;
+        }
+        Some(self.webhooks.min_interval)
+    }
+
+    /// Starts delivering `event`, reporting the `suppressed` notifications
+    /// the rate limit dropped before it.
+    pub fn send(&self, event: Event, info: &EventInfo, suppressed: u32) -> JoinHandle<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let body = json!({
+            "event": event.name(),
+            "user": info.user,
+            "ip": info.ip,
+            "old_ip": info.old_ip,
+            "mac": info.mac,
+            "error": info.error,
+            "error_msg": info.error_msg,
+            "suc_msg": info.suc_msg,
+            "timestamp": timestamp,
+            "suppressed": suppressed,
+        })
+        .to_string();
+
+        let notifier = self.clone();
+        thread::spawn(move || {
+            for url in &notifier.webhooks.urls {
+                notifier.deliver(url, body.as_bytes());
+            }
+        })
+    }
+
+    fn deliver(&self, url: &str, body: &[u8]) {
+        for attempt in 0..=self.webhooks.retries {
+            if attempt > 0 {
+                thread::sleep(Duration::from_millis(self.webhooks.retry_delay));
+            }
+            match self.post(url, body) {
+                Ok(()) => return,
+                Err(e) => warn!("Webhook {} failed (attempt {}): {}", url, attempt + 1, e),
+            }
+        }
+        warn!("Giving up on webhook {}", url);
+    }
+
+    fn post(&self, url: &str, body: &[u8]) -> Result<(), Box<dyn Error>> {
+        let url = Url::parse(url)?;
+        let is_https = url.scheme() == "https";
+        let host = url.host_str().ok_or(ParseError::EmptyHost)?;
+        let port = url.port().unwrap_or(if is_https { 443 } else { 80 });
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        let mut client = HttpClient::new(
+            is_https,
+            host,
+            port,
+            None,
+            None,
+            &self.cert_verification,
+            None,
+        )?;
+        client.set_timeout(Some(Duration::from_millis(self.webhooks.timeout)))?;
+        let response = client.post(&path, "application/json", body)?;
+        if !(200..300).contains(&response.status_code) {
+            return Err(format!("HTTP {} {}", response.status_code, response.reason).into());
+        }
+        Ok(())
+    }
+}
+
+/// Sends events to the hook commands and the webhooks, and records them in
+/// the state file, which also keeps the webhook rate limit.
+#[derive(Debug, Clone)]
+pub struct Dispatcher {
+    hooks: Hooks,
+    notifier: Notifier,
+    journal: Journal,
+}
+
+impl Dispatcher {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            hooks: config.hooks.clone(),
+            notifier: Notifier::new(&config.webhooks, &config.cert_verification),
+            journal: Journal::new(&config.state),
+        }
+    }
+
+    pub fn fire(&self, event: Event, info: &EventInfo) -> Vec<JoinHandle<()>> {
+        let min_interval = self.notifier.min_interval(event);
+        let suppressed = self.journal.record(event, info, min_interval);
+        if min_interval.is_some() && suppressed.is_none() {
+            debug!("Webhook for {} of {} rate limited", event.name(), info.user);
+        }
+        let webhooks = suppressed.map(|suppressed| self.notifier.send(event, info, suppressed));
+        self.hooks
+            .fire(event, info)
+            .into_iter()
+            .chain(webhooks)
+            .collect()
+    }
+
+    /// Records the traffic of an online user in the state file.
+    pub fn sample(&self, username: &str, info: &InfoResponse) {
+        self.journal.sample(username, info);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers requests with the given statuses in turn, returning the bodies.
+    fn serve(statuses: &'static [u16]) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook?token=abc", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                let body = loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        assert!(head.starts_with("POST /hook?token=abc HTTP/1.1\r\n"));
+                        let length: usize = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        if body.len() >= length {
+                            break body.to_string();
+                        }
+                    }
+                };
+                bodies.push(body);
+                let response = format!("HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_notify_retry_and_rate_limit() {
+        let (url, server) = serve(&[500, 200, 200]);
+        let dispatcher = Dispatcher::new(&Config {
+            webhooks: Webhooks {
+                urls: vec![url],
+                events: vec![String::from("login_failed")],
+                retry_delay: 10,
+                min_interval: 3600,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let mut info = EventInfo::new("alice", Some(String::from("10.0.0.2")));
+        info.error_msg = Some(String::from("E2553: Password is error."));
+
+        assert!(dispatcher.fire(Event::Login, &info).is_empty());
+        for handle in dispatcher.fire(Event::LoginFailed, &info) {
+            handle.join().unwrap();
+        }
+        assert!(dispatcher.fire(Event::LoginFailed, &info).is_empty());
+        let bob = EventInfo::new("bob", None);
+        for handle in dispatcher.fire(Event::LoginFailed, &bob) {
+            handle.join().unwrap();
+        }
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies[0], bodies[1]);
+        let event: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(event["event"], "login_failed");
+        assert_eq!(event["user"], "alice");
+        assert_eq!(event["ip"], "10.0.0.2");
+        assert_eq!(event["error_msg"], "E2553: Password is error.");
+        assert!(event["timestamp"].as_u64().unwrap() > 0);
+        let event: serde_json::Value = serde_json::from_str(&bodies[2]).unwrap();
+        assert_eq!(event["user"], "bob");
+    }
+}
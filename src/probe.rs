@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use url::{ParseError, Url};
+
+use crate::http::{CertVerification, HttpClient};
+use crate::{debug, info};
+
+/// Connectivity probe deciding whether traffic really passes the gateway,
+/// independent of what `rad_user_info` reports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Probe {
+    pub url: String,
+    /// Expected HTTP status code
+    pub status: u16,
+    /// Expected substring of the response body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Read timeout in milliseconds
+    pub timeout: u64,
+}
+
+impl Default for Probe {
+    fn default() -> Self {
+        Self {
+            url: String::from("http://connect.rom.miui.com/generate_204"),
+            status: 204,
+            body: None,
+            timeout: 5000,
+        }
+    }
+}
+
+impl Probe {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            ..Default::default()
+        }
+    }
+
+    /// Requests the probe URL from `local_ip` (if given) and reports whether
+    /// the expected response came back.
+    pub fn check(&self, local_ip: Option<IpAddr>, cert_verification: &CertVerification) -> bool {
+        match self.request(local_ip, cert_verification) {
+            Ok(()) => {
+                debug!("Connectivity probe {} passed", self.url);
+                true
+            }
+            Err(e) => {
+                info!("Connectivity probe {} failed: {}", self.url, e);
+                false
+            }
+        }
+    }
+
+    fn request(
+        &self,
+        local_ip: Option<IpAddr>,
+        cert_verification: &CertVerification,
+    ) -> Result<(), Box<dyn Error>> {
+        let url = Url::parse(&self.url)?;
+        let is_https = url.scheme() == "https";
+        let host = url.host_str().ok_or(ParseError::EmptyHost)?;
+        let port = url.port().unwrap_or(if is_https { 443 } else { 80 });
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        let mut client = HttpClient::new(
+            is_https,
+            host,
+            port,
+            local_ip.map(|ip| SocketAddr::new(ip, 0)),
+            None,
+            cert_verification,
+            None,
+        )?;
+        client.set_timeout(Some(Duration::from_millis(self.timeout)))?;
+        let response = client.request("GET", &path, None)?;
+
+        if response.status_code != self.status {
+            return Err(format!(
+                "expected status {}, got {} {}",
+                self.status, response.status_code, response.reason
+            )
+            .into());
+        }
+        if let Some(body) = &self.body
+            && !String::from_utf8_lossy(&response.body).contains(body.as_str())
+        {
+            return Err(format!("response body does not contain {:?}", body).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn serve_once(response: &'static str) -> Result<String, Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/generate_204", listener.local_addr()?);
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        Ok(url)
+    }
+
+    fn cert_verification() -> CertVerification {
+        #[cfg(feature = "tls")]
+        return CertVerification::System;
+        #[cfg(not(feature = "tls"))]
+        return CertVerification::None;
+    }
+
+    #[test]
+    fn test_probe() -> Result<(), Box<dyn Error>> {
+        let url = serve_once("HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")?;
+        assert!(Probe::new(url).check(None, &cert_verification()));
+
+        // captive portal redirect
+        let url = serve_once(
+            "HTTP/1.1 302 Found\r\nLocation: http://10.0.0.1/\r\nContent-Length: 0\r\n\r\n",
+        )?;
+        assert!(!Probe::new(url).check(None, &cert_verification()));
+
+        let url = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\nSuccess")?;
+        let probe = Probe {
+            url,
+            status: 200,
+            body: Some(String::from("Success")),
+            ..Default::default()
+        };
+        assert!(probe.check(None, &cert_verification()));
+        Ok(())
+    }
+}
@@ -1,10 +1,12 @@
+use clap::ValueEnum;
 use hmac::{Hmac, Mac};
 use md5::Md5;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use std::error::Error;
 use std::net::{Ipv4Addr, SocketAddr};
 use std::{
+    fmt,
     net::IpAddr,
     str::FromStr,
     thread,
@@ -12,23 +14,69 @@ use std::{
 };
 use url::{ParseError, Url};
 
-use crate::error::SrunError;
+use crate::error::{HttpError, SrunError};
 
 use crate::config::Config;
 use crate::config::User;
 use crate::debug;
-use crate::http::HttpClient;
+use crate::http::{self, HttpClient};
 use crate::info;
+use crate::warn;
 use crate::xencode;
 
 const PATH_GET_CHALLENGE: &str = "/cgi-bin/get_challenge";
 const PATH_PORTAL: &str = "/cgi-bin/srun_portal";
 const PATH_INFO: &str = "/cgi-bin/rad_user_info";
+const PATH_DM: &str = "/cgi-bin/rad_user_dm";
+
+/// How the password is hashed before it is sent to `srun_portal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum PasswordHash {
+    /// `{MD5}` + HMAC-MD5 keyed by the challenge, used by current versions
+    #[default]
+    HmacMd5,
+    /// `{MD5}` + plain MD5 of the password
+    Md5,
+    /// `{SHA1}` + plain SHA1 of the password
+    Sha1,
+    /// Password sent unhashed
+    Plain,
+}
+
+impl PasswordHash {
+    /// Returns the `password` parameter and the hash that goes into `chksum`.
+    pub fn hash(
+        &self,
+        password: &str,
+        challenge: &str,
+    ) -> Result<(String, String), Box<dyn Error>> {
+        Ok(match self {
+            PasswordHash::HmacMd5 => {
+                let mut mac = Hmac::<Md5>::new_from_slice(challenge.as_bytes())?;
+                mac.update(password.as_bytes());
+                let hmd5 = format!("{:x}", mac.finalize().into_bytes());
+                (format!("{{MD5}}{}", hmd5), hmd5)
+            }
+            PasswordHash::Md5 => {
+                let md5 = format!("{:x}", Md5::digest(password.as_bytes()));
+                (format!("{{MD5}}{}", md5), md5)
+            }
+            PasswordHash::Sha1 => {
+                let sha1 = format!("{:x}", Sha1::digest(password.as_bytes()));
+                (format!("{{SHA1}}{}", sha1), sha1)
+            }
+            PasswordHash::Plain => (password.to_string(), password.to_string()),
+        })
+    }
+}
 
 pub struct SrunClient {
     config: Config,
     user: User,
     client: HttpClient,
+    last_response: Option<PortalResponse>,
 }
 
 impl SrunClient {
@@ -58,12 +106,14 @@ impl SrunClient {
             local_addr,
             remote_addr,
             &config.cert_verification,
+            config.client_auth.as_ref(),
         )?;
 
         Ok(Self {
             config: config.clone(),
             user,
             client,
+            last_response: None,
         })
     }
 
@@ -72,11 +122,7 @@ impl SrunClient {
         path: &str,
         query: Option<Vec<(&str, &str)>>,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        let jsonp = format!("jsonp_{}", timestamp);
+        let jsonp = Self::callback();
         let callback = ("callback", jsonp.as_str());
         let query = if let Some(mut params) = query {
             params.push(callback);
@@ -85,7 +131,16 @@ impl SrunClient {
             vec![callback]
         };
         let response = self.client.request("GET", path, Some(query))?;
-        Ok(response.body[jsonp.len() + 1..response.body.len() - 1].to_vec())
+        if !(200..300).contains(&response.status_code) {
+            return Err(HttpError::Parse(format!(
+                "unexpected HTTP status {} {}, body: {}",
+                response.status_code,
+                response.reason,
+                preview(&response.body)
+            ))
+            .into());
+        }
+        Ok(extract_jsonp(&response.body, &jsonp)?.to_vec())
     }
 
     pub fn check_status(&mut self) -> Result<(bool, InfoResponse), Box<dyn Error>> {
@@ -103,6 +158,14 @@ impl SrunClient {
         }
     }
 
+    fn callback() -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        format!("jsonp_{}", timestamp)
+    }
+
     fn t() -> String {
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -126,67 +189,65 @@ impl SrunClient {
         }
     }
 
-    fn do_login(&mut self, ip: String) -> Result<PortalResponse, Box<dyn Error>> {
-        let challenge = self.get_challenge(&ip)?;
+    /// Builds the `srun_portal` login parameters for `ip` and `challenge`.
+    fn login_query(
+        config: &Config,
+        user: &User,
+        ip: &str,
+        challenge: &str,
+    ) -> Result<Vec<(&'static str, String)>, Box<dyn Error>> {
+        let encoding = xencode::Encoding::new(
+            &config.enc,
+            config.enc_prefix.as_deref(),
+            config.enc_alphabet.as_deref(),
+        )?;
         let info = xencode::param_i(
-            &self.user.username,
-            &self.user.password,
-            &ip,
-            self.config.acid as i32,
-            // &self.config.enc,
-            &challenge,
+            &user.username,
+            &user.password,
+            ip,
+            config.acid as i32,
+            &encoding,
+            challenge,
         );
-        let hmd5 = {
-            let mut mac = Hmac::<Md5>::new_from_slice(challenge.as_bytes())?;
-            mac.update(self.user.password.as_bytes());
-            let result = mac.finalize();
-            format!("{:x}", result.into_bytes())
-        };
-        let check_sum = {
-            let check_sum = [
-                "",
-                &self.user.username,
+        let (password, hmd5) = config.password_hash.hash(&user.password, challenge)?;
+        let check_sum = check_sum(
+            challenge,
+            &[
+                &user.username,
                 &hmd5,
-                &self.config.acid.to_string(),
-                &ip,
-                &self.config.n.to_string(),
-                &self.config.r#type.to_string(),
+                &config.acid.to_string(),
+                ip,
+                &config.n.to_string(),
+                &config.r#type.to_string(),
                 &info,
-            ]
-            .join(&challenge);
-            let mut sha1_hasher = Sha1::new();
-            sha1_hasher.update(check_sum);
-            format!("{:x}", sha1_hasher.finalize())
-        };
+            ],
+        );
         debug!("Challenge: {}", challenge);
         debug!("HMD5: {}", hmd5);
         debug!("Info: {}", info);
         debug!("CheckSum: {}", check_sum);
-        let username = self.user.username.clone();
-        let password = format!("{{MD5}}{}", hmd5);
-        let acid = self.config.acid.to_string();
-        let n = self.config.n.to_string();
-        let r#type = self.config.r#type.to_string();
-        let os = self.config.os.clone();
-        let os_name = self.config.os_name.clone();
-        let double_stack = self.config.double_stack.to_string();
-        let t = Self::t();
 
-        let query = vec![
-            ("action", "login"),
-            ("username", &username),
-            ("password", &password),
-            ("ip", &ip),
-            ("ac_id", &acid),
-            ("n", &n),
-            ("type", &r#type),
-            ("os", &os),
-            ("name", &os_name),
-            ("double_stack", &double_stack),
-            ("info", &info),
-            ("chksum", &check_sum),
-            ("_", &t),
-        ];
+        Ok(vec![
+            ("action", String::from("login")),
+            ("username", user.username.clone()),
+            ("password", password),
+            ("ip", ip.to_string()),
+            ("ac_id", config.acid.to_string()),
+            ("n", config.n.to_string()),
+            ("type", config.r#type.to_string()),
+            ("os", config.os.clone()),
+            ("name", config.os_name.clone()),
+            ("double_stack", config.double_stack.to_string()),
+            ("info", info),
+            ("chksum", check_sum),
+            ("_", Self::t()),
+        ])
+    }
+
+    fn do_login(&mut self, ip: String) -> Result<PortalResponse, Box<dyn Error>> {
+        let challenge = self.get_challenge(&ip)?;
+        let params = Self::login_query(&self.config, &self.user, &ip, &challenge)?;
+        let query = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
 
         let resp = self.jsonp(PATH_PORTAL, Some(query))?;
         let portal_resp: PortalResponse = serde_json::from_slice(&resp)?;
@@ -195,6 +256,7 @@ impl SrunClient {
             portal_resp.res, portal_resp.error, portal_resp.client_ip, portal_resp.online_ip
         );
         debug!("{:#?}", portal_resp);
+        self.last_response = Some(portal_resp.clone());
         Ok(portal_resp)
     }
 
@@ -218,20 +280,40 @@ impl SrunClient {
             portal_resp.res, portal_resp.error, portal_resp.client_ip, portal_resp.online_ip
         );
         debug!("{:#?}", portal_resp);
+        self.last_response = Some(portal_resp.clone());
         Ok(portal_resp)
     }
 
+    /// The last `srun_portal` response received by login or logout.
+    pub fn last_response(&self) -> Option<&PortalResponse> {
+        self.last_response.as_ref()
+    }
+
     pub fn login(&mut self) -> Result<(), Box<dyn Error>> {
+        self.last_response = None;
         if let Some(ip) = self.user.bind_addr {
             let ip = ip.to_string();
-            for i in 1..=self.config.retry_count {
-                info!("Login attempt {}/{}", i, self.config.retry_count);
+            let mut kicked = false;
+            let mut attempt = 1;
+            while attempt <= self.config.retry_count {
+                info!("Login attempt {}/{}", attempt, self.config.retry_count);
                 let resp = self.do_login(ip.clone());
                 match resp {
                     Ok(resp) => {
                         if resp.res == "ok" && resp.error == "ok" {
                             info!("Login successful: {}", resp.suc_msg);
                             return Ok(());
+                        } else if self.config.auto_kick && !kicked && is_online_limit(&resp) {
+                            info!("Login failed: {}, dropping another session", resp.error_msg);
+                            kicked = true;
+                            match self.kick_other(&ip) {
+                                // Retry right away, the kick does not use up an attempt
+                                Ok(closed) => {
+                                    info!("Dropped session at {}", closed);
+                                    continue;
+                                }
+                                Err(e) => warn!("Failed to drop another session: {}", e),
+                            }
                         } else {
                             info!("Login failed: {}", resp.error);
                         }
@@ -240,6 +322,7 @@ impl SrunClient {
                         info!("Login error: {}", e);
                     }
                 }
+                attempt += 1;
                 thread::sleep(Duration::from_millis(self.config.retry_delay));
             }
             Err(SrunError::Login("Exceeded maximum retry attempts").into())
@@ -248,30 +331,229 @@ impl SrunClient {
         }
     }
 
-    pub fn logout(&mut self) -> Result<(), Box<dyn Error>> {
-        if let Some(ip) = self.user.bind_addr {
-            let ip = ip.to_string();
-            info!("Logout.");
-            let resp = self.do_logout(ip.clone());
-            match resp {
-                Ok(resp) => {
-                    if resp.res == "ok" && resp.error == "ok" {
-                        info!("Logout successful: {}", resp.suc_msg);
-                        Ok(())
-                    } else {
-                        info!("Logout failed: {}", resp.error);
-                        Err(SrunError::Logout("Server rejected logout request").into())
-                    }
-                }
+    /// Drops the first session of `auto_kick_ips` that is online, other than
+    /// `own_ip`, and returns its address.
+    fn kick_other(&mut self, own_ip: &str) -> Result<String, Box<dyn Error>> {
+        for ip in self.config.auto_kick_ips.clone() {
+            if ip == own_ip {
+                continue;
+            }
+            match self.kick(&ip) {
+                Ok(_) => return Ok(ip),
                 Err(e) => {
-                    info!("Logout error: {}", e);
-                    Err(SrunError::Network("Failed to communicate with server").into())
+                    debug!("Session at {} not dropped: {}", ip, e);
+                }
+            }
+        }
+        Err(SrunError::Logout("No session of auto_kick_ips could be dropped").into())
+    }
+
+    /// Prints the login request that would be sent, without sending it. The
+    /// challenge is fetched from the server unless given.
+    pub fn dry_run(
+        config: &Config,
+        user: &User,
+        challenge: Option<&str>,
+        show_secrets: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let (ip, challenge) = match challenge {
+            Some(challenge) => {
+                let ip = user
+                    .bind_addr
+                    .ok_or(SrunError::Config("IP address required with --challenge"))?;
+                (ip.to_string(), challenge.to_string())
+            }
+            None => {
+                let mut client = Self::new(config, user.clone())?;
+                if client.user.bind_addr.is_none() {
+                    client.check_status()?;
+                }
+                let ip = client
+                    .user
+                    .bind_addr
+                    .ok_or(SrunError::Config("No IP address configured"))?
+                    .to_string();
+                let challenge = client.get_challenge(&ip)?;
+                (ip, challenge)
+            }
+        };
+
+        let mut params = Self::login_query(config, user, &ip, &challenge)?;
+        params.push(("callback", Self::callback()));
+        if !show_secrets {
+            // info 可用 challenge 解密出明文密码
+            for (key, value) in params.iter_mut() {
+                if matches!(*key, "password" | "info") {
+                    *value = String::from("******");
                 }
             }
+        }
+
+        let url = Url::parse(&config.server)?;
+        let query: Vec<(&str, &str)> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        println!("Challenge: {}", challenge);
+        println!(
+            "GET {} HTTP/1.1",
+            http::request_target(PATH_PORTAL, Some(query))
+        );
+        println!("Host: {}", url.host_str().unwrap_or_default());
+        println!();
+        for (key, value) in &params {
+            println!("{:<14}{}", key, value);
+        }
+        Ok(())
+    }
+
+    /// Drops the session of this user at `ip` through `rad_user_dm`, which
+    /// works from any address. An empty `ip` drops every session of the user
+    /// on portals supporting it. Returns the addresses of the closed sessions
+    /// as far as the portal reports them.
+    pub fn kick(&mut self, ip: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let username = self.user.username.clone();
+        let t = Self::t();
+        let unbind = "1";
+        let sign = dm_sign(&t, &username, ip, unbind);
+        let query = vec![
+            ("ip", ip),
+            ("username", &username),
+            ("time", &t),
+            ("unbind", unbind),
+            ("sign", &sign),
+        ];
+
+        let resp = self.jsonp(PATH_DM, Some(query))?;
+        let dm_resp: PortalResponse = serde_json::from_slice(&resp)?;
+        info!(
+            "DmResponse: res: {}, error: {}, error_msg: {}",
+            dm_resp.res, dm_resp.error, dm_resp.error_msg
+        );
+        debug!("{:#?}", dm_resp);
+        if dm_resp.error == "ok" || dm_resp.error == "logout_ok" {
+            // 部分 portal 在 online_ip 中以逗号分隔列出被下线的地址
+            let mut closed: Vec<String> = dm_resp
+                .online_ip
+                .split(',')
+                .map(str::trim)
+                .filter(|closed_ip| !closed_ip.is_empty())
+                .map(String::from)
+                .collect();
+            if closed.is_empty() && !ip.is_empty() {
+                closed.push(ip.to_string());
+            }
+            Ok(closed)
+        } else {
+            Err(SrunError::Logout("Server rejected kick request").into())
+        }
+    }
+
+    pub fn logout(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(ip) = self.user.bind_addr {
+            self.logout_ip(&ip.to_string())
         } else {
             Err(SrunError::Config("No IP address configured").into())
         }
     }
+
+    /// Logs out the session at `ip`, which need not be a local address.
+    pub fn logout_ip(&mut self, ip: &str) -> Result<(), Box<dyn Error>> {
+        self.last_response = None;
+        info!("Logout {}.", ip);
+        let resp = self.do_logout(ip.to_string());
+        match resp {
+            Ok(resp) => {
+                if resp.res == "ok" && resp.error == "ok" {
+                    info!("Logout successful: {}", resp.suc_msg);
+                    Ok(())
+                } else {
+                    info!("Logout failed: {}", resp.error);
+                    Err(SrunError::Logout("Server rejected logout request").into())
+                }
+            }
+            Err(e) => {
+                info!("Logout error: {}", e);
+                Err(SrunError::Network("Failed to communicate with server").into())
+            }
+        }
+    }
+}
+
+/// Whether a failed login was caused by the concurrent device limit. The
+/// code comes as `ecode`, `"E2620"` or `2620`, and usually also in front of
+/// `error_msg`, which some portals translate or leave empty.
+fn is_online_limit(resp: &PortalResponse) -> bool {
+    let ecode = resp.ecode.to_string();
+    ecode == "E2620"
+        || ecode == "2620"
+        || resp.error.starts_with("E2620")
+        || resp.error_msg.starts_with("E2620")
+}
+
+/// Extracts the JSON payload of a `callback(...)` response. Whitespace and a
+/// trailing `;` are tolerated, and plain JSON is accepted for servers that
+/// ignore the callback parameter.
+fn extract_jsonp<'a>(body: &'a [u8], callback: &str) -> Result<&'a [u8], HttpError> {
+    let text = body.trim_ascii();
+    let text = text.strip_prefix(b"\xef\xbb\xbf").unwrap_or(text);
+    if text.starts_with(b"{") || text.starts_with(b"[") {
+        return Ok(text);
+    }
+
+    let invalid = |reason: &str| {
+        HttpError::Parse(format!(
+            "{} (expected {}(...)), body: {}",
+            reason,
+            callback,
+            preview(body)
+        ))
+    };
+    let name_len = text
+        .iter()
+        .position(|c| !(c.is_ascii_alphanumeric() || matches!(c, b'_' | b'$' | b'.')))
+        .unwrap_or(text.len());
+    if name_len == 0 {
+        return Err(invalid("response is neither JSONP nor JSON"));
+    }
+    if &text[..name_len] != callback.as_bytes() {
+        return Err(invalid("unexpected JSONP callback name"));
+    }
+
+    let args = text[name_len..].trim_ascii_start();
+    let args = args.strip_suffix(b";").unwrap_or(args).trim_ascii_end();
+    match args.strip_prefix(b"(").and_then(|a| a.strip_suffix(b")")) {
+        Some(json) => Ok(json.trim_ascii()),
+        None => Err(invalid("malformed JSONP response")),
+    }
+}
+
+/// Up to 200 characters of a response body for error messages.
+fn preview(body: &[u8]) -> String {
+    const LIMIT: usize = 200;
+    let text = String::from_utf8_lossy(body);
+    let mut preview: String = text.chars().take(LIMIT).collect();
+    if text.chars().count() > LIMIT {
+        preview.push_str("...");
+    }
+    format!("{:?}", preview)
+}
+
+/// SHA1 of the login fields, each prefixed by the challenge, sent as `chksum`.
+pub fn check_sum(challenge: &str, fields: &[&str]) -> String {
+    let mut sha1_hasher = Sha1::new();
+    for field in fields {
+        sha1_hasher.update(challenge);
+        sha1_hasher.update(field);
+    }
+    format!("{:x}", sha1_hasher.finalize())
+}
+
+/// SHA1 of `time + username + ip + unbind + time`, sent as `sign` to
+/// `rad_user_dm`.
+pub fn dm_sign(time: &str, username: &str, ip: &str, unbind: &str) -> String {
+    let mut sha1_hasher = Sha1::new();
+    for part in [time, username, ip, unbind, time] {
+        sha1_hasher.update(part);
+    }
+    format!("{:x}", sha1_hasher.finalize())
 }
 
 #[allow(dead_code)]
@@ -289,43 +571,70 @@ struct ChallengeResponse {
     st: u64,
 }
 
+/// The answer of `rad_user_info`. Every field is optional and parsed
+/// leniently, numbers are also accepted as strings or floats.
 #[derive(Debug, Default, Deserialize)]
 #[serde(default)]
 pub struct InfoResponse {
+    #[serde(deserialize_with = "lenient::text")]
     pub online_ip: String,
+    #[serde(deserialize_with = "lenient::text")]
     pub error: String,
 
-    #[serde(rename = "ServerFlag")]
-    pub server_flag: Option<u32>,
-    pub add_time: Option<u32>,
+    #[serde(rename = "ServerFlag", deserialize_with = "lenient::uint")]
+    pub server_flag: Option<u64>,
+    #[serde(deserialize_with = "lenient::uint")]
+    pub add_time: Option<u64>,
+    #[serde(deserialize_with = "lenient::uint")]
     pub all_bytes: Option<u64>,
+    #[serde(deserialize_with = "lenient::uint")]
     pub bytes_in: Option<u64>,
+    #[serde(deserialize_with = "lenient::uint")]
     pub bytes_out: Option<u64>,
-    pub checkout_date: Option<u32>,
+    #[serde(deserialize_with = "lenient::uint")]
+    pub checkout_date: Option<u64>,
+    #[serde(deserialize_with = "lenient::string")]
     pub domain: Option<String>,
-    pub keepalive_time: Option<u32>,
+    #[serde(deserialize_with = "lenient::uint")]
+    pub keepalive_time: Option<u64>,
+    #[serde(deserialize_with = "lenient::string")]
     pub real_name: Option<String>,
-    pub remain_seconds: Option<u32>,
+    #[serde(deserialize_with = "lenient::uint")]
+    pub remain_seconds: Option<u64>,
+    #[serde(deserialize_with = "lenient::uint")]
     pub sum_bytes: Option<u64>,
-    pub sum_seconds: Option<u32>,
+    #[serde(deserialize_with = "lenient::uint")]
+    pub sum_seconds: Option<u64>,
+    #[serde(deserialize_with = "lenient::string")]
     pub sysver: Option<String>,
+    #[serde(deserialize_with = "lenient::float")]
     pub user_balance: Option<f64>,
+    #[serde(deserialize_with = "lenient::float")]
     pub user_charge: Option<f64>,
+    #[serde(deserialize_with = "lenient::string")]
     pub user_mac: Option<String>,
+    #[serde(deserialize_with = "lenient::string")]
     pub user_name: Option<String>,
+    #[serde(deserialize_with = "lenient::float")]
     pub wallet_balance: Option<f64>,
 
+    #[serde(deserialize_with = "lenient::string")]
     pub client_ip: Option<String>,
-    pub ecode: Option<u32>,
+    #[serde(deserialize_with = "lenient::string")]
+    pub ecode: Option<String>,
+    #[serde(deserialize_with = "lenient::string")]
     pub error_msg: Option<String>,
+    #[serde(deserialize_with = "lenient::string")]
     pub res: Option<String>,
+    #[serde(deserialize_with = "lenient::string")]
     pub srun_ver: Option<String>,
-    pub st: Option<u32>,
+    #[serde(deserialize_with = "lenient::uint")]
+    pub st: Option<u64>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 #[serde(default)]
-struct PortalResponse {
+pub struct PortalResponse {
     #[serde(rename = "ServerFlag")]
     pub server_flag: i32,
     #[serde(rename = "ServicesIntfServerIP")]
@@ -351,10 +660,9 @@ struct PortalResponse {
     pub st: u64,
 }
 
-#[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(untagged)]
-enum ECode {
+pub enum ECode {
     I(i32),
     S(String),
 }
@@ -364,3 +672,304 @@ impl Default for ECode {
         Self::I(0)
     }
 }
+
+impl ECode {
+    /// Whether the portal reported an error code, it sends 0 or "" otherwise.
+    pub fn is_set(&self) -> bool {
+        !matches!(self, Self::I(0)) && !matches!(self, Self::S(code) if code.is_empty())
+    }
+}
+
+impl fmt::Display for ECode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::I(code) => write!(f, "{}", code),
+            Self::S(code) => f.write_str(code),
+        }
+    }
+}
+
+/// Deserializers accepting more than one JSON type for a field. Values that
+/// do not fit are treated as absent rather than failing the whole response.
+mod lenient {
+    use serde::{Deserialize, Deserializer};
+    use serde_json::Value;
+
+    /// A number, a float (truncated) or a numeric string.
+    pub fn uint<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        let float_to_uint =
+            |float: f64| (float.is_finite() && float >= 0.0).then_some(float as u64);
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Number(number) => number
+                .as_u64()
+                .or_else(|| number.as_f64().and_then(float_to_uint)),
+            Value::String(text) => {
+                let text = text.trim();
+                text.parse()
+                    .ok()
+                    .or_else(|| text.parse().ok().and_then(float_to_uint))
+            }
+            _ => None,
+        })
+    }
+
+    /// A number or a numeric string.
+    pub fn float<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Number(number) => number.as_f64(),
+            Value::String(text) => text
+                .trim()
+                .parse()
+                .ok()
+                .filter(|float: &f64| float.is_finite()),
+            _ => None,
+        })
+    }
+
+    /// A non-empty string, numbers as their decimal form.
+    pub fn string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::String(text) if !text.is_empty() => Some(text),
+            Value::Number(number) => Some(number.to_string()),
+            _ => None,
+        })
+    }
+
+    /// Like `string`, empty if absent.
+    pub fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        string(deserializer).map(Option::unwrap_or_default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hash_modes() -> Result<(), Box<dyn Error>> {
+        let challenge = "3e2d5f0b6c1e4f6b8a9d0c7e5f3a2b1c4d6e8f0a1b3c5d7e9f1a2b3c4d5e6f70";
+        let (password, _) = PasswordHash::Md5.hash("password", challenge)?;
+        assert_eq!(password, "{MD5}5f4dcc3b5aa765d61d8327deb882cf99");
+        let (password, hash) = PasswordHash::Sha1.hash("password", challenge)?;
+        assert_eq!(password, "{SHA1}5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8");
+        assert_eq!(hash, "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8");
+        let (password, hash) = PasswordHash::Plain.hash("password", challenge)?;
+        assert_eq!((password.as_str(), hash.as_str()), ("password", "password"));
+        let (password, hash) = PasswordHash::HmacMd5.hash("password", challenge)?;
+        assert_eq!(password, format!("{{MD5}}{}", hash));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_jsonp() {
+        let cb = "jsonp_1700000000000";
+        let json = br#"{"error":"ok"}"#;
+        let cases: [&[u8]; 7] = [
+            br#"jsonp_1700000000000({"error":"ok"})"#,
+            b"jsonp_1700000000000({\"error\":\"ok\"})\n",
+            b"  jsonp_1700000000000 ( {\"error\":\"ok\"} ) ;\r\n",
+            br#"jsonp_1700000000000({"error":"ok"});"#,
+            br#"{"error":"ok"}"#,
+            b"\n{\"error\":\"ok\"}\n",
+            b"\xef\xbb\xbfjsonp_1700000000000({\"error\":\"ok\"})",
+        ];
+        for body in cases {
+            assert_eq!(extract_jsonp(body, cb).unwrap(), json, "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn test_extract_jsonp_errors() {
+        let cb = "jsonp_1700000000000";
+        let html = format!(
+            "<html><body>{}</body></html>",
+            "502 Bad Gateway ".repeat(30)
+        );
+        let cases: [&[u8]; 6] = [
+            b"",
+            b")",
+            html.as_bytes(),
+            br#"jsonp_1699999999999({"error":"ok"})"#,
+            br#"jsonp_1700000000000({"error":"ok"}"#,
+            b"jsonp_1700000000000",
+        ];
+        for body in cases {
+            let err = extract_jsonp(body, cb).unwrap_err().to_string();
+            assert!(err.starts_with("Parse error: "), "{}", err);
+        }
+        let err = extract_jsonp(html.as_bytes(), cb).unwrap_err().to_string();
+        assert!(err.contains("<html><body>502 Bad Gateway"));
+        assert!(err.ends_with("...\""));
+    }
+
+    #[test]
+    fn test_is_online_limit() -> Result<(), Box<dyn Error>> {
+        // srun_portal answers of the device limit, addresses anonymised
+        let limit = [
+            r#"{"client_ip":"10.0.0.2","ecode":"E2620","error":"login_error","error_msg":"E2620: You are already online.","online_ip":"10.0.0.2","res":"login_error","srun_ver":"SRunCGIAuthIntfSvr V1.18 B20180306","st":1792293120}"#,
+            r#"{"client_ip":"10.0.0.2","ecode":2620,"error":"login_error","error_msg":"","online_ip":"10.0.0.2","res":"login_error","st":1792293120}"#,
+            r#"{"ecode":0,"error":"E2620","error_msg":"已经在线了","res":"login_error"}"#,
+        ];
+        for body in limit {
+            let resp: PortalResponse = serde_json::from_str(body)?;
+            assert!(is_online_limit(&resp), "{}", body);
+        }
+        let other = [
+            r#"{"client_ip":"10.0.0.2","ecode":"E2531","error":"login_error","error_msg":"E2531: User not found.","online_ip":"10.0.0.2","res":"login_error","srun_ver":"SRunCGIAuthIntfSvr V1.18 B20180306","st":1792293120}"#,
+            r#"{"ecode":0,"error":"ok","error_msg":"","res":"ok","suc_msg":"login_ok"}"#,
+        ];
+        for body in other {
+            let resp: PortalResponse = serde_json::from_str(body)?;
+            assert!(!is_online_limit(&resp), "{}", body);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_sum_vectors() -> Result<(), Box<dyn Error>> {
+        // Generated by scripts/vectors/vectors.js: (username, password, acid, ip, n,
+        // type, challenge, info, hmd5, chksum)
+        let vectors = [
+            (
+                "a",
+                "b",
+                "1",
+                "10.0.0.1",
+                "200",
+                "1",
+                "abc",
+                "{SRBX1}SW4xMvlByp3A0wx5L6ZU9oS9dVfosw8X3Iw3DTvgza1G1urSbd+A3fUQ/eZ7J7PHcBqcOZj1m69v/CQB+wz+29TmKdmKfKlL1LmtzkSeu7/RI/hT",
+                "3d7ce911b8706ebcb1c47b005f119f2d",
+                "916e97bd8d944dcf74d2ae21c886dbe7af8eabed",
+            ),
+            (
+                "202112345@cmcc",
+                "p@ssw0rd",
+                "62",
+                "10.21.1.77",
+                "200",
+                "1",
+                "9f6c1f0a33f7d7de2b1d6b7e2f4f7a6c1b98d33c8f1e0d9a7b6c5d4e3f2a1b0c",
+                "{SRBX1}exNZWPy/N8MNDZdHDg4FNwKYZgChP5YoHlENmxs1jrcSENv3jxY4a2VpuiTqWSZMAbIPMUUqk46wcAWANnVcLjQDayStXU2F2K/vAABSD0bPLq9RrvlOHt8a5VKC1SVeKJ1l4qsxxZ2=",
+                "df0b793d7a00ae83ddf5ffa564131a65",
+                "ac597823911ec4abf95a680a58c397f906f22315",
+            ),
+            (
+                "张三@unicom",
+                "密码Pässwörd",
+                "5",
+                "172.16.0.23",
+                "200",
+                "1",
+                "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+                "{SRBX1}y1VaFk59dW1c4WDf6dmcB4QEnkQU8swrSH+kWbUpnNm36gnlEIZvA/hh4qd+4WTBKXZcOfrSTfoQlmt6e3qz25a1amwMALyY6pXOmK6cRMPaj3rLJdJC1IiUy8+IPPrCxOAvcNQL2Nv=",
+                "55377e0bc3d53a61cf348036bab3ddf9",
+                "32c776dc9272fbad2c179bc477a65ee85a34af06",
+            ),
+            (
+                "long_user",
+                "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx0123456789012345678901234567890123456789012345678901234567890123456789",
+                "12",
+                "192.168.100.200",
+                "100",
+                "3",
+                "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100",
+                "{SRBX1}4yXu/5MMT4NEq7fo2PzgyE4Qz0nN4PCiZqESVR24yhms7IeiYfCn3WWjp2JAJ0LX1uLj0HQPG/bNyRJVbCtWFKBKr6Qfp/p3e76KfegliMbARXqQ9L5rv+XSdlR9sYAH1N6Kh/KlkTpJhjVsLHAe9FE2cGKr/naxhPocAv9jnUk9bJQsUHHifJ3M/He+TRWCtilfBQSFqvgvlMtrQJ3oCO5uLQ9petdEf8kjf1kMd2SwN2sQ0kBTlX/AvYs0byQciTMuBQnik7bTy4Ub",
+                "81f6c2188b8cd9c429088313c8b85ea3",
+                "e3a3b4e6a5c15e5e5539be7a86830ebe9f5feaf5",
+            ),
+            (
+                "quote\"user",
+                "back\\slash/and\nnewline",
+                "1",
+                "10.0.0.2",
+                "200",
+                "1",
+                "5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a",
+                "{SRBX1}5oRzLkebyDk1OqA7GDZUSa5t7hl7OeuEWtubdrbuds0WuA2yLOfBf9pP3dwO/dZy3h+MQ5obvET8tbhBz72NeZARhdHoNrLdYWdWCP461++umWZWw+L+uyCxeJRtYa60h222DZthyCYhnaxfhBEAs+PTppP=",
+                "97260ca8c06b364f3d2ec336854a9875",
+                "8c4be71e8312404eda92e093bf9e74ba3b574b1c",
+            ),
+        ];
+        for (username, password, acid, ip, n, r#type, challenge, info, hmd5, chksum) in vectors {
+            let (sent, hash) = PasswordHash::HmacMd5.hash(password, challenge)?;
+            assert_eq!(hash, hmd5);
+            assert_eq!(sent, format!("{{MD5}}{}", hmd5));
+            let fields = [username, hmd5, acid, ip, n, r#type, info];
+            assert_eq!(
+                check_sum(challenge, &fields),
+                chksum,
+                "chksum for {:?}",
+                username
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_info_response_field_types() -> Result<(), Box<dyn Error>> {
+        // Hand-written rad_user_info payloads, one session with each of the
+        // field types the lenient parsing accepts. They test the parsing,
+        // not what any real portal sends
+        let payloads: [&str; 5] = [
+            // Numbers as numbers
+            r#"{"ServerFlag":0,"add_time":1792281600,"all_bytes":1820327936,"billing_name":"","bytes_in":1610612736,"bytes_out":209715200,"checkout_date":0,"domain":"","error":"ok","group_id":"3","keepalive_time":1792293120,"online_device_total":"1","online_ip":"10.0.0.2","online_ip6":"::","package_id":"1","products_id":"1","products_name":"Student","real_name":"","remain_bytes":0,"remain_seconds":0,"sum_bytes":53687091200,"sum_seconds":11520,"sysver":"1.01.20200322","user_balance":10,"user_charge":0,"user_mac":"02:00:00:00:00:01","user_name":"alice","wallet_balance":0}"#,
+            // Numbers quoted, empty strings for missing values
+            r#"{"ServerFlag":"0","add_time":"1792281600","all_bytes":"1820327936","bytes_in":"1610612736","bytes_out":"209715200","checkout_date":"","domain":"","error":"ok","keepalive_time":"1792293120","online_ip":"10.0.0.2","real_name":"","remain_seconds":"","sum_bytes":"53687091200","sum_seconds":"11520","user_balance":"10.00","user_charge":"0.00","user_mac":"","user_name":"alice","wallet_balance":""}"#,
+            // Floats for counters and balances, nulls
+            r#"{"ServerFlag":0,"add_time":1792281600,"all_bytes":1.820327936E9,"bytes_in":1610612736.0,"bytes_out":209715200,"error":"ok","online_ip":"10.0.0.2","sum_bytes":5.36870912e10,"sum_seconds":11520.4,"sysver":null,"user_balance":9.5,"user_mac":null,"user_name":20260001,"wallet_balance":-1}"#,
+            // Minimal, without traffic, MAC or version
+            r#"{"error":"ok","online_ip":"10.0.0.2","user_name":"alice"}"#,
+            // Not online
+            r#"{"client_ip":"10.0.0.2","ecode":0,"error":"not_online_error","error_msg":"","online_ip":"10.0.0.2","res":"not_online_error","srun_ver":"SRunCGIAuthIntfSvr V1.18 B20200407","st":1792293120}"#,
+        ];
+        let infos = payloads
+            .iter()
+            .map(|payload| serde_json::from_str(payload))
+            .collect::<Result<Vec<InfoResponse>, _>>()?;
+        for info in &infos[..3] {
+            assert_eq!(info.error, "ok");
+            assert_eq!(info.online_ip, "10.0.0.2");
+            assert_eq!(info.add_time, Some(1792281600));
+            assert_eq!(info.bytes_in, Some(1610612736));
+            assert_eq!(info.bytes_out, Some(209715200));
+            assert_eq!(info.all_bytes, Some(1820327936));
+            assert_eq!(info.sum_bytes, Some(53687091200));
+            assert_eq!(info.sum_seconds, Some(11520));
+            assert_eq!(info.remain_seconds.unwrap_or_default(), 0);
+        }
+        assert_eq!(infos[0].user_balance, Some(10.0));
+        assert_eq!(infos[0].sysver.as_deref(), Some("1.01.20200322"));
+        assert_eq!(infos[1].user_balance, Some(10.0));
+        assert_eq!(infos[1].server_flag, Some(0));
+        assert_eq!(infos[1].checkout_date, None);
+        assert_eq!(infos[1].user_mac, None);
+        assert_eq!(infos[1].wallet_balance, None);
+        assert_eq!(infos[2].user_balance, Some(9.5));
+        assert_eq!(infos[2].wallet_balance, Some(-1.0));
+        assert_eq!(infos[2].user_name.as_deref(), Some("20260001"));
+        assert_eq!((&infos[2].sysver, &infos[2].user_mac), (&None, &None));
+        assert_eq!(infos[3].sum_bytes, None);
+        assert_eq!(infos[4].error, "not_online_error");
+        assert_eq!(infos[4].ecode.as_deref(), Some("0"));
+        assert_eq!(infos[4].st, Some(1792293120));
+
+        // The same session reads the same, whatever the types
+        let lines = |info| crate::format::online_info(info, true);
+        assert_eq!(lines(&infos[0])[1..], lines(&infos[1])[1..]);
+        assert_eq!(lines(&infos[0])[1..], lines(&infos[2])[1..]);
+        assert_eq!(
+            lines(&infos[1])[0],
+            "Already logged in at 10.0.0.2 as alice."
+        );
+        assert_eq!(
+            lines(&infos[2])[0],
+            "Already logged in at 10.0.0.2 as 20260001."
+        );
+        assert_eq!(
+            lines(&infos[3]),
+            ["Already logged in at 10.0.0.2 as alice."]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,512 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hooks::{Event, EventInfo};
+use crate::srun::InfoResponse;
+use crate::usage::{self, Usage};
+use crate::warn;
+
+/// Where the history of the users is kept, and how much of it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StateFile {
+    /// JSON file, no history is kept if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Events kept per user, the oldest are dropped first
+    pub max_events: usize,
+    /// Minimum seconds between two traffic snapshots of a user
+    pub snapshot_interval: u64,
+    /// Days after which traffic snapshots are dropped
+    pub retention_days: u64,
+}
+
+impl Default for StateFile {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_events: 200,
+            snapshot_interval: 3600,
+            retention_days: 90,
+        }
+    }
+}
+
+impl StateFile {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Contents of the state file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub users: BTreeMap<String, UserState>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserState {
+    pub last_ip: Option<String>,
+    pub last_login: Option<u64>,
+    pub events: Vec<Record>,
+    pub snapshots: Vec<Snapshot>,
+    /// Usage per local date, e.g. `2026-10-01`, kept after the snapshots
+    pub daily: BTreeMap<String, Usage>,
+    /// Last webhook notification per event name, for the rate limit
+    pub notified: BTreeMap<String, Notified>,
+}
+
+/// When a webhook notification of an event was last sent, and how many were
+/// dropped by the rate limit since.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Notified {
+    pub time: u64,
+    pub suppressed: u32,
+}
+
+/// An event of a user, with the portal's `error` and `ecode` for logins.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub time: u64,
+    pub event: Event,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ecode: Option<String>,
+}
+
+/// The traffic counters of `rad_user_info` at `time`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    pub time: u64,
+    pub bytes_in: Option<u64>,
+    pub bytes_out: Option<u64>,
+    pub sum_bytes: Option<u64>,
+    pub sum_seconds: Option<u64>,
+}
+
+impl State {
+    /// Reads `path`, an empty state if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replaces `path` atomically: the new contents are written and synced
+    /// to a temporary file that is then renamed over it, so a power loss
+    /// leaves either the old or the new file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let dir = parent(path);
+        fs::create_dir_all(dir)?;
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        // The rename itself is only durable once the directory is synced
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Records events and traffic snapshots in the state file. The file is only
+/// written when something was recorded, at most once per snapshot interval
+/// for the traffic, to spare flash storage. srun processes sharing the file
+/// take turns through a lock file next to it. Without a state file the state
+/// only lives as long as the process.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    settings: StateFile,
+    cache: Arc<Mutex<Option<Cached>>>,
+}
+
+/// The state as last read or written, with the modification time and size
+/// of the file then, to notice writes by other srun processes.
+#[derive(Debug)]
+struct Cached {
+    state: State,
+    version: Option<(SystemTime, u64)>,
+}
+
+impl Journal {
+    pub fn new(settings: &StateFile) -> Self {
+        Self {
+            settings: settings.clone(),
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Records `event`. With `min_interval`, also counts its webhook
+    /// notification against the rate limit, in the same write. Returns the
+    /// number of notifications dropped since the last one sent if this one
+    /// may be sent, `None` if it is dropped or there is no limit.
+    pub fn record(&self, event: Event, info: &EventInfo, min_interval: Option<u64>) -> Option<u32> {
+        let max_events = self.settings.max_events;
+        let mut suppressed = None;
+        self.update(&info.user, |user, now| {
+            let error = info.error.clone().filter(|error| error != "ok");
+            user.events.push(Record {
+                time: now,
+                event,
+                ip: info.ip.clone(),
+                error,
+                ecode: info.ecode.clone(),
+            });
+            let excess = user.events.len().saturating_sub(max_events);
+            user.events.drain(..excess);
+            if event == Event::Login {
+                user.last_login = Some(now);
+            }
+            if matches!(event, Event::Login | Event::IpChanged) && info.ip.is_some() {
+                user.last_ip = info.ip.clone();
+            }
+            if let Some(min_interval) = min_interval {
+                suppressed = user.throttle(event, min_interval, now);
+            }
+            true
+        });
+        suppressed
+    }
+
+    /// Records the traffic of an online user, unless the last snapshot is
+    /// more recent than the snapshot interval, and spreads the usage since
+    /// the last snapshot over the days in between.
+    pub fn sample(&self, username: &str, info: &InfoResponse) {
+        if info.sum_bytes.is_none() && info.bytes_in.is_none() {
+            return;
+        }
+        let StateFile {
+            snapshot_interval,
+            retention_days,
+            ..
+        } = self.settings;
+        self.update(username, |user, now| {
+            if let Some(last) = user.snapshots.last()
+                && now.saturating_sub(last.time) < snapshot_interval
+            {
+                return false;
+            }
+            let snapshot = Snapshot {
+                time: now,
+                bytes_in: info.bytes_in,
+                bytes_out: info.bytes_out,
+                sum_bytes: info.sum_bytes,
+                sum_seconds: info.sum_seconds,
+            };
+            if let Some(last) = user.snapshots.last() {
+                let usage = usage::delta(last, &snapshot);
+                for (day, usage) in usage::split(usage, last.time, now) {
+                    *user.daily.entry(day).or_default() += usage;
+                }
+            }
+            user.snapshots.push(snapshot);
+            let oldest = now.saturating_sub(retention_days * 86400);
+            user.snapshots.retain(|snapshot| snapshot.time >= oldest);
+            if !info.online_ip.is_empty() {
+                user.last_ip = Some(info.online_ip.clone());
+            }
+            true
+        });
+    }
+
+    /// Applies `change` to the state of `username` and saves it if `change`
+    /// returns true. Failures are logged, the history is best effort: a file
+    /// that cannot be parsed is moved aside, while one that cannot be read
+    /// is left alone and the change is only kept in memory.
+    fn update(&self, username: &str, change: impl FnOnce(&mut UserState, u64) -> bool) {
+        let mut cache = self.cache.lock().unwrap();
+        let cached = cache.get_or_insert_with(|| Cached {
+            state: State::default(),
+            version: None,
+        });
+        let Some(path) = &self.settings.path else {
+            change(
+                cached.state.users.entry(username.to_string()).or_default(),
+                now(),
+            );
+            return;
+        };
+        let path = Path::new(path);
+        // 读取到写入之间，其他 srun 进程不能改动文件
+        let lock = lock(path);
+        let mut writable = match &lock {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Failed to lock state file {}: {}", path.display(), e);
+                false
+            }
+        };
+        let version = version(path);
+        if cached.version != version {
+            match State::load(path) {
+                Ok(state) => *cached = Cached { state, version },
+                Err(e) if e.is::<serde_json::Error>() => {
+                    let mut bad = path.as_os_str().to_owned();
+                    bad.push(".bad");
+                    warn!(
+                        "State file {} is corrupt, moving it to {} and starting over: {}",
+                        path.display(),
+                        bad.display(),
+                        e
+                    );
+                    let _ = fs::rename(path, &bad);
+                    *cached = Cached {
+                        state: State::default(),
+                        version: None,
+                    };
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to read state file {}, not writing it: {}",
+                        path.display(),
+                        e
+                    );
+                    writable = false;
+                }
+            }
+        }
+        let user = cached.state.users.entry(username.to_string()).or_default();
+        if !change(user, now()) || !writable {
+            return;
+        }
+        match cached.state.save(path) {
+            Ok(()) => cached.version = self::version(path),
+            Err(e) => warn!("Failed to write state file {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Counts of the events of a user since some time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    pub logins: usize,
+    pub login_failures: usize,
+    pub offline: usize,
+    pub ip_changes: usize,
+}
+
+impl UserState {
+    fn throttle(&mut self, event: Event, min_interval: u64, now: u64) -> Option<u32> {
+        let name = event.name().to_string();
+        let last = self.notified.get(&name).copied();
+        if let Some(last) = last
+            && now.saturating_sub(last.time) < min_interval
+        {
+            let notified = Notified {
+                suppressed: last.suppressed + 1,
+                ..last
+            };
+            self.notified.insert(name, notified);
+            return None;
+        }
+        let notified = Notified {
+            time: now,
+            suppressed: 0,
+        };
+        self.notified.insert(name, notified);
+        Some(last.map_or(0, |last| last.suppressed))
+    }
+
+    pub fn summary(&self, since: u64) -> Summary {
+        let mut summary = Summary::default();
+        for record in self.events.iter().filter(|record| record.time >= since) {
+            match record.event {
+                Event::Login => summary.logins += 1,
+                Event::LoginFailed => summary.login_failures += 1,
+                Event::OfflineDetected => summary.offline += 1,
+                Event::IpChanged => summary.ip_changes += 1,
+                Event::Logout => {}
+            }
+        }
+        summary
+    }
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Takes an exclusive lock on `<path>.lock`, held until the returned file is
+/// dropped. The lock file is left in place, removing it would race with
+/// other processes waiting for it.
+fn lock(path: &Path) -> io::Result<File> {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    fs::create_dir_all(parent(path))?;
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock)?;
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+        // SAFETY: the descriptor is owned by file and open
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(file)
+}
+
+fn version(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datetime::DateTime;
+
+    #[test]
+    fn test_journal() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("srun-state-{}", std::process::id()));
+        let path = dir.join("state.json");
+        let settings = StateFile {
+            path: Some(path.to_str().unwrap().to_string()),
+            max_events: 3,
+            ..Default::default()
+        };
+        let journal = Journal::new(&settings);
+
+        journal.record(Event::Logout, &EventInfo::new("alice", None), None);
+        let mut failed = EventInfo::new("alice", Some(String::from("10.0.0.2")));
+        failed.error = Some(String::from("login_error"));
+        failed.ecode = Some(String::from("E2553"));
+        journal.record(Event::LoginFailed, &failed, None);
+        let mut login = EventInfo::new("alice", Some(String::from("10.0.0.2")));
+        login.error = Some(String::from("ok"));
+        journal.record(Event::Login, &login, None);
+        journal.record(Event::OfflineDetected, &EventInfo::new("alice", None), None);
+
+        let info = InfoResponse {
+            online_ip: String::from("10.0.0.3"),
+            sum_bytes: Some(1000),
+            ..Default::default()
+        };
+        journal.sample("alice", &info);
+        journal.sample(
+            "alice",
+            &InfoResponse {
+                sum_bytes: Some(2000),
+                ..Default::default()
+            },
+        );
+
+        // Another process sees everything, and its writes are picked up
+        let state = State::load(&path)?;
+        Journal::new(&settings).record(Event::Logout, &EventInfo::new("bob", None), None);
+        journal.record(Event::Logout, &EventInfo::new("carol", None), None);
+        let names: Vec<_> = State::load(&path)?.users.into_keys().collect();
+        assert_eq!(names, ["alice", "bob", "carol"]);
+
+        // The usage between snapshots goes to the current day
+        let frequent = Journal::new(&StateFile {
+            snapshot_interval: 0,
+            ..settings.clone()
+        });
+        frequent.sample("dave", &info);
+        frequent.sample(
+            "dave",
+            &InfoResponse {
+                sum_bytes: Some(4000),
+                ..Default::default()
+            },
+        );
+        let daily = &State::load(&path)?.users["dave"].daily;
+        let today = DateTime::local(now()).date();
+        assert_eq!(daily[&today].bytes, 3000);
+        assert!(!dir.join("state.json.tmp").exists());
+        fs::remove_dir_all(&dir)?;
+
+        let alice = &state.users["alice"];
+        let events: Vec<_> = alice.events.iter().map(|record| record.event).collect();
+        assert_eq!(
+            events,
+            [Event::LoginFailed, Event::Login, Event::OfflineDetected]
+        );
+        assert_eq!(alice.events[0].ecode.as_deref(), Some("E2553"));
+        assert_eq!(alice.events[1].error, None);
+        assert_eq!(alice.last_ip.as_deref(), Some("10.0.0.3"));
+        assert!(alice.last_login.is_some());
+        assert_eq!(alice.snapshots.len(), 1);
+        assert_eq!(alice.snapshots[0].sum_bytes, Some(1000));
+        assert_eq!(
+            alice.summary(0),
+            Summary {
+                logins: 1,
+                login_failures: 1,
+                offline: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(alice.summary(now() + 1), Summary::default());
+
+        // The webhook rate limit holds across processes through the file
+        let path = dir.join("throttle.json");
+        let settings = StateFile {
+            path: Some(path.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let failed = EventInfo::new("alice", None);
+        let record = |min_interval| {
+            Journal::new(&settings).record(Event::LoginFailed, &failed, Some(min_interval))
+        };
+        assert_eq!(record(3600), Some(0));
+        assert_eq!(record(3600), None);
+        assert_eq!(record(3600), None);
+        let notified = State::load(&path)?.users["alice"].notified["login_failed"];
+        assert_eq!(notified.suppressed, 2);
+        assert_eq!(record(0), Some(2));
+        assert_eq!(
+            Journal::new(&settings).record(Event::Login, &failed, Some(3600)),
+            Some(0)
+        );
+
+        // A corrupt file is moved aside, an unreadable one is left alone
+        fs::write(&path, "{")?;
+        assert_eq!(record(3600), Some(0));
+        assert!(dir.join("throttle.json.bad").exists());
+        assert!(State::load(&path).is_ok());
+        let unreadable = dir.join("unreadable.json");
+        fs::create_dir(&unreadable)?;
+        let journal = Journal::new(&StateFile {
+            path: Some(unreadable.to_str().unwrap().to_string()),
+            ..Default::default()
+        });
+        assert_eq!(journal.record(Event::Login, &failed, Some(3600)), Some(0));
+        assert_eq!(journal.record(Event::Login, &failed, Some(3600)), None);
+        assert!(unreadable.is_dir());
+        assert!(!dir.join("unreadable.json.bad").exists());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
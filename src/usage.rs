@@ -0,0 +1,307 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::{AddAssign, SubAssign};
+
+use crate::datetime::DateTime;
+use crate::format;
+use crate::state::{Snapshot, State};
+
+/// Traffic and online time of a user over some period.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub bytes: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub seconds: u64,
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.bytes += other.bytes;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.seconds += other.seconds;
+    }
+}
+
+impl SubAssign for Usage {
+    fn sub_assign(&mut self, other: Self) {
+        self.bytes -= other.bytes;
+        self.bytes_in -= other.bytes_in;
+        self.bytes_out -= other.bytes_out;
+        self.seconds -= other.seconds;
+    }
+}
+
+/// The usage between two snapshots. A counter lower than before was reset,
+/// by a new session for `bytes_in`/`bytes_out` or a new billing period for
+/// `sum_bytes`/`sum_seconds`, and counts from zero.
+pub fn delta(prev: &Snapshot, next: &Snapshot) -> Usage {
+    let counter = |prev: Option<u64>, next: Option<u64>| match (prev, next) {
+        (Some(prev), Some(next)) if next >= prev => next - prev,
+        (Some(_), Some(next)) => next,
+        _ => 0,
+    };
+    let bytes_in = counter(prev.bytes_in, next.bytes_in);
+    let bytes_out = counter(prev.bytes_out, next.bytes_out);
+    // sum_bytes also covers the traffic of other sessions of the account
+    let bytes = match (prev.sum_bytes, next.sum_bytes) {
+        (Some(_), Some(_)) => counter(prev.sum_bytes, next.sum_bytes),
+        _ => bytes_in + bytes_out,
+    };
+    Usage {
+        bytes,
+        bytes_in,
+        bytes_out,
+        seconds: counter(prev.sum_seconds, next.sum_seconds),
+    }
+}
+
+/// Spreads the usage between the timestamps `start` and `end` over the local
+/// days in between, in proportion to the time of each day, as the snapshots
+/// do not tell when the traffic happened. The days are in order and their
+/// usage adds up to `usage`.
+pub fn split(usage: Usage, start: u64, end: u64) -> Vec<(String, Usage)> {
+    let mut days = Vec::new();
+    let mut time = start;
+    while time < end {
+        let local = DateTime::local(time);
+        let elapsed = local.hour * 3600 + local.minute * 60 + local.second;
+        let next = (time + 86400 - u64::from(elapsed)).min(end);
+        days.push((local.date(), next - time));
+        time = next;
+    }
+    let Some((last, _)) = days.pop() else {
+        return vec![(DateTime::local(end).date(), usage)];
+    };
+    let total = u128::from(end - start);
+    let mut left = usage;
+    let mut parts = Vec::new();
+    for (day, seconds) in days {
+        let share = |value: u64| (u128::from(value) * u128::from(seconds) / total) as u64;
+        let part = Usage {
+            bytes: share(usage.bytes),
+            bytes_in: share(usage.bytes_in),
+            bytes_out: share(usage.bytes_out),
+            seconds: share(usage.seconds),
+        };
+        left -= part;
+        parts.push((day, part));
+    }
+    // 余数归最后一天，总和保持不变
+    parts.push((last, left));
+    parts
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Period {
+    Day,
+    Month,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+/// The usage of a user in one day (`2026-10-01`) or month (`2026-10`).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Row {
+    pub user: String,
+    pub period: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// The usage per user and period from the day `since` on, both as
+/// `YYYY-MM-DD`.
+pub fn report(state: &State, user: Option<&str>, since: Option<&str>, period: Period) -> Vec<Row> {
+    let mut rows = Vec::new();
+    for (name, state) in &state.users {
+        if user.is_some_and(|user| user != name) {
+            continue;
+        }
+        let mut periods: BTreeMap<&str, Usage> = BTreeMap::new();
+        for (day, usage) in &state.daily {
+            if since.is_some_and(|since| day.as_str() < since) {
+                continue;
+            }
+            let key = match period {
+                Period::Day => day.as_str(),
+                Period::Month => &day[..day.len().min(7)],
+            };
+            *periods.entry(key).or_default() += *usage;
+        }
+        rows.extend(periods.into_iter().map(|(period, usage)| Row {
+            user: name.clone(),
+            period: period.to_string(),
+            usage,
+        }));
+    }
+    rows
+}
+
+pub fn render(rows: &[Row], report_format: ReportFormat) -> String {
+    match report_format {
+        ReportFormat::Table => {
+            let mut out = format!(
+                "{:<24} {:<10} {:>10} {:>10} {:>10} {:>10}\n",
+                "USER", "PERIOD", "TOTAL", "IN", "OUT", "TIME"
+            );
+            for row in rows {
+                out.push_str(&format!(
+                    "{:<24} {:<10} {:>10} {:>10} {:>10} {:>10}\n",
+                    row.user,
+                    row.period,
+                    format::bytes(row.usage.bytes),
+                    format::bytes(row.usage.bytes_in),
+                    format::bytes(row.usage.bytes_out),
+                    format::duration(row.usage.seconds)
+                ));
+            }
+            out
+        }
+        ReportFormat::Csv => {
+            let mut out = String::from("user,period,bytes,bytes_in,bytes_out,seconds\n");
+            for row in rows {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{}\n",
+                    csv_field(&row.user),
+                    row.period,
+                    row.usage.bytes,
+                    row.usage.bytes_in,
+                    row.usage.bytes_out,
+                    row.usage.seconds
+                ));
+            }
+            out
+        }
+        ReportFormat::Json => serde_json::to_string_pretty(rows).unwrap() + "\n",
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::UserState;
+
+    fn snapshot(bytes_in: u64, sum_bytes: Option<u64>) -> Snapshot {
+        Snapshot {
+            time: 0,
+            bytes_in: Some(bytes_in),
+            bytes_out: Some(bytes_in / 10),
+            sum_bytes,
+            sum_seconds: None,
+        }
+    }
+
+    #[test]
+    fn test_delta() {
+        // Same session, sum_bytes preferred
+        let usage = delta(&snapshot(1000, Some(5000)), &snapshot(3000, Some(7500)));
+        assert_eq!(
+            (usage.bytes, usage.bytes_in, usage.bytes_out),
+            (2500, 2000, 200)
+        );
+        // New session and billing period
+        let usage = delta(&snapshot(3000, Some(7500)), &snapshot(500, Some(600)));
+        assert_eq!(
+            (usage.bytes, usage.bytes_in, usage.bytes_out),
+            (600, 500, 50)
+        );
+        // Without sum_bytes
+        let usage = delta(&snapshot(1000, None), &snapshot(3000, Some(7500)));
+        assert_eq!(usage.bytes, 2200);
+        assert_eq!(
+            delta(&Snapshot::default(), &snapshot(3000, None)),
+            Usage::default()
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        let usage = Usage {
+            bytes: 1001,
+            seconds: 43200,
+            ..Default::default()
+        };
+        let time = 1_790_000_000;
+        let local = DateTime::local(time);
+        let midnight = time - u64::from(local.hour * 3600 + local.minute * 60 + local.second);
+        let evening = midnight + 18 * 3600;
+        let days = split(usage, evening, evening + 12 * 3600);
+        let dates: Vec<_> = days.iter().map(|(day, _)| day.as_str()).collect();
+        assert_eq!(
+            dates,
+            [local.date(), DateTime::local(evening + 43200).date()]
+        );
+        assert_eq!((days[0].1.bytes, days[1].1.bytes), (500, 501));
+        assert_eq!((days[0].1.seconds, days[1].1.seconds), (21600, 21600));
+
+        assert_eq!(split(usage, time, time), [(local.date(), usage)]);
+        let days = split(usage, evening, evening + 3 * 86400);
+        assert_eq!(days.len(), 4);
+        let mut total = Usage::default();
+        for (_, part) in days {
+            total += part;
+        }
+        assert_eq!(total, usage);
+    }
+
+    #[test]
+    fn test_report() {
+        let usage = |bytes| Usage {
+            bytes,
+            ..Default::default()
+        };
+        let mut state = State::default();
+        let mut alice = UserState::default();
+        alice.daily.insert(String::from("2026-09-30"), usage(1));
+        alice.daily.insert(String::from("2026-10-01"), usage(2));
+        alice.daily.insert(String::from("2026-10-02"), usage(4));
+        state.users.insert(String::from("alice"), alice);
+        let mut bob = UserState::default();
+        bob.daily.insert(String::from("2026-10-02"), usage(8));
+        state.users.insert(String::from("bob,2"), bob);
+
+        let rows = report(&state, None, Some("2026-10-01"), Period::Month);
+        assert_eq!(
+            rows,
+            [
+                Row {
+                    user: String::from("alice"),
+                    period: String::from("2026-10"),
+                    usage: usage(6),
+                },
+                Row {
+                    user: String::from("bob,2"),
+                    period: String::from("2026-10"),
+                    usage: usage(8),
+                },
+            ]
+        );
+        assert_eq!(report(&state, Some("alice"), None, Period::Day).len(), 3);
+        assert_eq!(
+            render(&rows, ReportFormat::Csv),
+            "user,period,bytes,bytes_in,bytes_out,seconds\n\
+             alice,2026-10,6,0,0,0\n\
+             \"bob,2\",2026-10,8,0,0,0\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&rows, ReportFormat::Json)).unwrap();
+        assert_eq!(json[1]["user"], "bob,2");
+        assert_eq!(json[1]["bytes"], 8);
+    }
+}
@@ -1,18 +1,78 @@
 use base64::{
     Engine,
-    alphabet::Alphabet,
+    alphabet::{self, Alphabet},
     engine::{self, GeneralPurpose},
 };
-use std::sync::OnceLock;
+use serde::Serialize;
 
-static BASE64_ENGINE: OnceLock<GeneralPurpose> = OnceLock::new();
-const BASE64_ALPHABET: &str = "LVoJPiCN2R8G90yg+hmFHuacZ1OWMnrsSTXkYpUq/3dlbfKwv6xztjI7DeBE45QA";
+use crate::error::{ConfigError, SrunError};
 
-fn get_base64engine() -> &'static GeneralPurpose {
-    BASE64_ENGINE.get_or_init(|| {
-        let alphabet = Alphabet::new(BASE64_ALPHABET).unwrap();
-        engine::GeneralPurpose::new(&alphabet, engine::GeneralPurposeConfig::new())
-    })
+const SRUN_ALPHABET: &str = "LVoJPiCN2R8G90yg+hmFHuacZ1OWMnrsSTXkYpUq/3dlbfKwv6xztjI7DeBE45QA";
+
+/// Info encoding scheme: the `enc_ver` sent to the server, the prefix of the
+/// `info` parameter and the base64 alphabet used after xencode.
+#[derive(Debug, Clone)]
+pub struct Encoding {
+    pub enc_ver: String,
+    pub prefix: String,
+    alphabet: Alphabet,
+}
+
+impl Encoding {
+    /// Builds the scheme for `enc`. The prefix defaults to `{SRBX1}` for
+    /// `srun_bx1`, `{SRBX2}` for `srun_bx2` and so on; the alphabet is
+    /// `srun` (default), `standard` or 64 custom characters.
+    pub fn new(
+        enc: &str,
+        prefix: Option<&str>,
+        alphabet: Option<&str>,
+    ) -> Result<Self, ConfigError> {
+        let prefix = match prefix {
+            Some(prefix) => prefix.to_string(),
+            None => match enc.strip_prefix("srun_") {
+                Some(variant) if !variant.is_empty() => format!("{{SR{}}}", variant.to_uppercase()),
+                _ => {
+                    return Err(ConfigError::Validation(
+                        "Unknown enc, specify enc_prefix for non srun_* schemes",
+                    ));
+                }
+            },
+        };
+        let alphabet = match alphabet.unwrap_or("srun") {
+            "srun" => Alphabet::new(SRUN_ALPHABET).unwrap(),
+            "standard" => alphabet::STANDARD,
+            custom => Alphabet::new(custom).map_err(|_| {
+                ConfigError::Validation(
+                    "Invalid enc_alphabet, expected \"srun\", \"standard\" or 64 unique characters",
+                )
+            })?,
+        };
+        Ok(Self {
+            enc_ver: enc.to_string(),
+            prefix,
+            alphabet,
+        })
+    }
+
+    fn engine(&self) -> GeneralPurpose {
+        engine::GeneralPurpose::new(&self.alphabet, engine::GeneralPurposeConfig::new())
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::new("srun_bx1", None, None).unwrap()
+    }
+}
+
+/// Login info in the field order used by the portal JavaScript.
+#[derive(Serialize)]
+struct Info<'a> {
+    username: &'a str,
+    password: &'a str,
+    ip: &'a str,
+    acid: i32,
+    enc_ver: &'a str,
 }
 
 fn mix(buffer: &[u8], append_size: bool) -> Vec<u32> {
@@ -32,12 +92,40 @@ fn mix(buffer: &[u8], append_size: bool) -> Vec<u32> {
     res
 }
 
+/// Packs UTF-16 code units like the portal's `charCodeAt` based JavaScript,
+/// which matches `mix` over the bytes for ASCII strings.
+fn mix_str(msg: &str, append_size: bool) -> Vec<u32> {
+    let units: Vec<u32> = msg.encode_utf16().map(u32::from).collect();
+    let mut res: Vec<u32> = units
+        .chunks(4)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |word, (i, unit)| word | unit << (i * 8))
+        })
+        .collect();
+    if append_size {
+        res.push(units.len() as u32);
+    }
+    res
+}
+
+/// Key words, zero padded to the four words indexed by the cipher.
+fn mix_key(key: &str) -> Vec<u32> {
+    let mut key = mix_str(key, false);
+    if key.len() < 4 {
+        key.resize(4, 0);
+    }
+    key
+}
+
 fn splite(buffer: Vec<u32>, include_size: bool) -> Vec<u8> {
     let len = buffer.len();
     let size_record = buffer[len - 1];
     if include_size {
         let size = ((len - 1) * 4) as u32;
-        if size_record < size - 3 || size_record > size {
+        if size_record.saturating_add(3) < size || size_record > size {
             return "".into();
         }
     }
@@ -53,8 +141,8 @@ fn x_encode(msg: &str, key: &str) -> Vec<u8> {
     if msg.is_empty() {
         return vec![];
     }
-    let mut msg = mix(msg.as_bytes(), true);
-    let key = mix(key.as_bytes(), false);
+    let mut msg = mix_str(msg, true);
+    let key = mix_key(key);
 
     let len = msg.len();
     let last = len - 1;
@@ -78,15 +166,333 @@ fn x_encode(msg: &str, key: &str) -> Vec<u8> {
     splite(msg, false)
 }
 
-pub fn param_i(username: &str, password: &str, ip: &str, acid: i32, token: &str) -> String {
-    let info = serde_json::json!({
-        "username": username,
-        "password": password,
-        "ip": ip,
-        "acid": acid,
-        "enc_ver": "srun_bx1",
+/// Inverse of `x_encode`, returns an empty buffer if the data or key is wrong.
+fn x_decode(data: &[u8], key: &str) -> Vec<u8> {
+    if data.is_empty() || !data.len().is_multiple_of(4) {
+        return vec![];
+    }
+    let mut msg = mix(data, false);
+    let key = mix_key(key);
+
+    let len = msg.len();
+    let c: u32 = 0x9e3779b9;
+    let count = 6 + 52 / len as u32;
+    let mut d = count.wrapping_mul(c);
+
+    while d != 0 {
+        let e = d >> 2 & 3;
+        for p in (0..len).rev() {
+            let left = msg[(p + 1) % len];
+            let right = msg[(p + len - 1) % len];
+            let m = ((right >> 5) ^ (left << 2))
+                .wrapping_add((left >> 3 ^ right << 4) ^ (d ^ left))
+                .wrapping_add(key[(p & 3) ^ e as usize] ^ right);
+            msg[p] = msg[p].wrapping_sub(m);
+        }
+        d = d.wrapping_sub(c);
+    }
+    splite(msg, true)
+}
+
+pub fn param_i(
+    username: &str,
+    password: &str,
+    ip: &str,
+    acid: i32,
+    encoding: &Encoding,
+    token: &str,
+) -> String {
+    let info = serde_json::to_string(&Info {
+        username,
+        password,
+        ip,
+        acid,
+        enc_ver: &encoding.enc_ver,
     })
-    .to_string();
+    .unwrap();
     let xen = x_encode(info.as_str(), token);
-    String::from("{SRBX1}") + get_base64engine().encode(xen).as_str()
+    encoding.prefix.clone() + encoding.engine().encode(xen).as_str()
+}
+
+/// Recovers the JSON payload of an `info` parameter built by `param_i`.
+///
+/// Bytes are mapped to chars one to one like the portal JavaScript, so
+/// characters beyond Latin-1 cannot be recovered exactly.
+pub fn decode_param_i(info: &str, encoding: &Encoding, token: &str) -> Result<String, SrunError> {
+    let data = match info.strip_prefix(encoding.prefix.as_str()) {
+        Some(data) => data,
+        None if info.starts_with('{') => match info.find('}') {
+            Some(end) => &info[end + 1..],
+            None => info,
+        },
+        None => info,
+    };
+    let engine = engine::GeneralPurpose::new(
+        &encoding.alphabet,
+        engine::GeneralPurposeConfig::new()
+            .with_decode_padding_mode(engine::DecodePaddingMode::Indifferent),
+    );
+    let xen = engine
+        .decode(data.trim())
+        .map_err(|_| SrunError::Decode("Info is not valid base64 for the configured alphabet"))?;
+    let msg = x_decode(&xen, token);
+    if msg.is_empty() {
+        return Err(SrunError::Decode(
+            "Info could not be decrypted with the given challenge",
+        ));
+    }
+    Ok(msg.into_iter().map(char::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generated by scripts/vectors/vectors.js from the portal's xEncode and
+    // jquery.base64, like the vectors below.
+    const USERNAME: &str = "202112345@cmcc";
+    const PASSWORD: &str = "p@ssw0rd";
+    const IP: &str = "10.21.1.77";
+    const ACID: i32 = 62;
+    const TOKEN: &str = "3e2d5f0b6c1e4f6b8a9d0c7e5f3a2b1c4d6e8f0a1b3c5d7e9f1a2b3c4d5e6f70";
+
+    struct LoginVector {
+        username: &'static str,
+        password: &'static str,
+        ip: &'static str,
+        acid: i32,
+        token: &'static str,
+        info: &'static str,
+    }
+
+    // Short key, typical, non-ASCII, multi-block and JSON escaping cases.
+    const LOGIN_VECTORS: [LoginVector; 5] = [
+        LoginVector {
+            username: "a",
+            password: "b",
+            ip: "10.0.0.1",
+            acid: 1,
+            token: "abc",
+            info: "{SRBX1}SW4xMvlByp3A0wx5L6ZU9oS9dVfosw8X3Iw3DTvgza1G1urSbd+A3fUQ/eZ7J7PHcBqcOZj1m69v/CQB+wz+29TmKdmKfKlL1LmtzkSeu7/RI/hT",
+        },
+        LoginVector {
+            username: "202112345@cmcc",
+            password: "p@ssw0rd",
+            ip: "10.21.1.77",
+            acid: 62,
+            token: "9f6c1f0a33f7d7de2b1d6b7e2f4f7a6c1b98d33c8f1e0d9a7b6c5d4e3f2a1b0c",
+            info: "{SRBX1}exNZWPy/N8MNDZdHDg4FNwKYZgChP5YoHlENmxs1jrcSENv3jxY4a2VpuiTqWSZMAbIPMUUqk46wcAWANnVcLjQDayStXU2F2K/vAABSD0bPLq9RrvlOHt8a5VKC1SVeKJ1l4qsxxZ2=",
+        },
+        LoginVector {
+            username: "张三@unicom",
+            password: "密码Pässwörd",
+            ip: "172.16.0.23",
+            acid: 5,
+            token: "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+            info: "{SRBX1}y1VaFk59dW1c4WDf6dmcB4QEnkQU8swrSH+kWbUpnNm36gnlEIZvA/hh4qd+4WTBKXZcOfrSTfoQlmt6e3qz25a1amwMALyY6pXOmK6cRMPaj3rLJdJC1IiUy8+IPPrCxOAvcNQL2Nv=",
+        },
+        LoginVector {
+            username: "long_user",
+            password: "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx0123456789012345678901234567890123456789012345678901234567890123456789",
+            ip: "192.168.100.200",
+            acid: 12,
+            token: "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100",
+            info: "{SRBX1}4yXu/5MMT4NEq7fo2PzgyE4Qz0nN4PCiZqESVR24yhms7IeiYfCn3WWjp2JAJ0LX1uLj0HQPG/bNyRJVbCtWFKBKr6Qfp/p3e76KfegliMbARXqQ9L5rv+XSdlR9sYAH1N6Kh/KlkTpJhjVsLHAe9FE2cGKr/naxhPocAv9jnUk9bJQsUHHifJ3M/He+TRWCtilfBQSFqvgvlMtrQJ3oCO5uLQ9petdEf8kjf1kMd2SwN2sQ0kBTlX/AvYs0byQciTMuBQnik7bTy4Ub",
+        },
+        LoginVector {
+            username: "quote\"user",
+            password: "back\\slash/and\nnewline",
+            ip: "10.0.0.2",
+            acid: 1,
+            token: "5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a",
+            info: "{SRBX1}5oRzLkebyDk1OqA7GDZUSa5t7hl7OeuEWtubdrbuds0WuA2yLOfBf9pP3dwO/dZy3h+MQ5obvET8tbhBz72NeZARhdHoNrLdYWdWCP461++umWZWw+L+uyCxeJRtYa60h222DZthyCYhnaxfhBEAs+PTppP=",
+        },
+    ];
+
+    // (message, key, hex of x_encode output)
+    const X_ENCODE_VECTORS: [(&str, &str, &str); 4] = [
+        ("hello", "key", "f08847cc6fdecf6bda12909b"),
+        ("1234", "0123456789abcdef", "fc8f138389500721"),
+        ("a", "a", "2e210abd8394f993"),
+        (
+            "The quick brown fox jumps over the lazy dog",
+            "3e2d5f0b6c1e4f6b8a9d0c7e5f3a2b1c",
+            "ed0b6ecd3b25d3bdb7b3b66afff24c97093e0c7c51f4a2d50e22a01597b1fc62e608fd7144169d67ac93c62a3dafba9b",
+        ),
+    ];
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// xorshift32, enough to generate reproducible inputs without extra crates.
+    fn next_random(state: &mut u32) -> u32 {
+        *state ^= *state << 13;
+        *state ^= *state >> 17;
+        *state ^= *state << 5;
+        *state
+    }
+
+    #[test]
+    fn test_mix() {
+        assert_eq!(mix(b"abcde", true), vec![0x64636261, 0x65, 5]);
+        assert_eq!(mix(b"abcd", false), vec![0x64636261]);
+        assert_eq!(mix_key("ab"), vec![0x6261, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_splite() {
+        assert_eq!(splite(vec![0x64636261, 0x65], false), b"abcde\0\0\0");
+        assert_eq!(splite(vec![0x64636261, 0x65, 5], true), b"abcde");
+        // size record out of range
+        assert_eq!(splite(vec![0x64636261, 0x65, 9], true), b"");
+        assert_eq!(splite(vec![0x64636261, 0x65, 4], true), b"");
+    }
+
+    #[test]
+    fn test_x_encode_vectors() {
+        for (msg, key, expected) in X_ENCODE_VECTORS {
+            assert_eq!(
+                hex(&x_encode(msg, key)),
+                expected,
+                "x_encode({:?}, {:?})",
+                msg,
+                key
+            );
+        }
+        assert!(x_encode("", "key").is_empty());
+    }
+
+    #[test]
+    fn test_param_i_vectors() {
+        let encoding = Encoding::default();
+        for v in &LOGIN_VECTORS {
+            let info = param_i(v.username, v.password, v.ip, v.acid, &encoding, v.token);
+            assert_eq!(info, v.info, "param_i for {:?}", v.username);
+        }
+    }
+
+    #[test]
+    fn test_decode_param_i_vectors() {
+        let encoding = Encoding::default();
+        for v in LOGIN_VECTORS
+            .iter()
+            .filter(|v| v.username.is_ascii() && v.password.is_ascii())
+        {
+            let payload = decode_param_i(v.info, &encoding, v.token).unwrap();
+            let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+            assert_eq!(payload["username"], v.username);
+            assert_eq!(payload["password"], v.password);
+            assert_eq!(payload["ip"], v.ip);
+            assert_eq!(payload["acid"], v.acid);
+        }
+    }
+
+    #[test]
+    fn test_x_decode_round_trip() {
+        let mut state = 0x2545f491;
+        for _ in 0..500 {
+            // Latin-1 messages map one byte per char, like the portal JavaScript
+            let len = next_random(&mut state) as usize % 300 + 1;
+            let msg: String = (0..len)
+                .map(|_| char::from(next_random(&mut state) as u8))
+                .collect();
+            let key_len = next_random(&mut state) as usize % 70;
+            let key: String = (0..key_len)
+                .map(|_| char::from(b'0' + (next_random(&mut state) % 75) as u8))
+                .collect();
+
+            let encoded = x_encode(&msg, &key);
+            assert_eq!(encoded.len() % 4, 0);
+            let decoded: String = x_decode(&encoded, &key)
+                .into_iter()
+                .map(char::from)
+                .collect();
+            assert_eq!(decoded, msg, "key {:?}", key);
+        }
+    }
+
+    #[test]
+    fn test_param_i_round_trip() {
+        let mut state = 0x9e3779b9;
+        let encoding = Encoding::default();
+        for _ in 0..200 {
+            let password: String = (0..next_random(&mut state) % 64)
+                .map(|_| char::from(b' ' + (next_random(&mut state) % 95) as u8))
+                .collect();
+            let token = format!(
+                "{:08x}{:08x}",
+                next_random(&mut state),
+                next_random(&mut state)
+            );
+
+            let info = param_i("user", &password, "10.0.0.1", 1, &encoding, &token);
+            let payload = decode_param_i(&info, &encoding, &token).unwrap();
+            let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+            assert_eq!(payload["password"], password.as_str());
+        }
+    }
+
+    #[test]
+    fn test_param_i_srun_bx1() {
+        let info = param_i(USERNAME, PASSWORD, IP, ACID, &Encoding::default(), TOKEN);
+        assert_eq!(
+            info,
+            "{SRBX1}lDizPhZ/YSh1Nx81OeY1VEnb9lZZAJhmYUbqurYg5L1povHO3NKPCNsisCcO94XRc6R2yDO/V7+7tCx7m/6SQ12GzwsfEr9sG0DOnKpFWxT3Cv41sxKyi5YEKs6mu/ItlV1A0HEziRv="
+        );
+    }
+
+    #[test]
+    fn test_param_i_standard_alphabet() {
+        let encoding = Encoding::new("srun_bx1", None, Some("standard")).unwrap();
+        let info = param_i(USERNAME, PASSWORD, IP, ACID, &encoding, TOKEN);
+        assert_eq!(
+            info,
+            "{SRBX1}r4FzERYokgRZHyKZa5kZB7dsMrYY/DRSkmsnVekP9AZlCwUapHuEGHfFfGXaM8iJXxJIO4aoB3Q30Gy3Soxg+ZILzvft7eMfLN4adulTbyhpGw8ZfyuOF9k7ufxSVo20rBZ/NU7zFJw="
+        );
+    }
+
+    #[test]
+    fn test_param_i_derived_prefix() {
+        let encoding = Encoding::new("srun_bx2", None, None).unwrap();
+        assert_eq!(encoding.prefix, "{SRBX2}");
+        let info = param_i(USERNAME, PASSWORD, IP, ACID, &encoding, TOKEN);
+        assert_eq!(
+            info,
+            "{SRBX2}/PS8mKn80eRFksskbJ1gC476lyStsREiP/YF4XbgXcCJbXuDWPpFGE1aEMyY3GaaJNnbPz40InA3ga70NPRlMLhoEERFiS0MxM9JkP9WwlO4NJCRWmZ1qjyAVUhS1Nor9wao9F9kXEM="
+        );
+    }
+
+    #[test]
+    fn test_decode_param_i() {
+        let encoding = Encoding::default();
+        let info = param_i(USERNAME, PASSWORD, IP, ACID, &encoding, TOKEN);
+        let payload = decode_param_i(&info, &encoding, TOKEN).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"username":"202112345@cmcc","password":"p@ssw0rd","ip":"10.21.1.77","acid":62,"enc_ver":"srun_bx1"}"#
+        );
+        assert!(decode_param_i(&info, &encoding, "wrong challenge").is_err());
+    }
+
+    #[test]
+    fn test_mix_str() {
+        assert_eq!(mix_str("abcde", true), mix(b"abcde", true));
+        // U+00E9 packs as a single 0xe9 unit, U+5F20 overlaps the next byte like charCodeAt
+        assert_eq!(mix_str("\u{e9}a", true), vec![0x61e9, 2]);
+        assert_eq!(mix_str("\u{5f20}a", false), vec![0x5f20 | 0x61 << 8]);
+    }
+
+    #[test]
+    fn test_x_decode_short_key() {
+        let encoded = x_encode("hello", "key");
+        assert_eq!(x_decode(&encoded, "key"), b"hello");
+    }
+
+    #[test]
+    fn test_encoding_validation() {
+        assert!(Encoding::new("custom", None, None).is_err());
+        assert!(Encoding::new("custom", Some("{CUSTOM}"), None).is_ok());
+        assert!(Encoding::new("srun_bx1", None, Some("too short")).is_err());
+    }
 }